@@ -1,28 +1,37 @@
-use image::ImageBuffer;
 use obj::Obj;
+use pbrt::bsdf::ThinFilm;
 use pbrt::color::Color;
+use pbrt::ies::IesProfile;
+use pbrt::integrator::bdpt::BdptIntegrator;
+use pbrt::integrator::debug::{DebugChannel, DebugIntegrator};
+use pbrt::integrator::mlt::MltIntegrator;
+use pbrt::integrator::path::PathIntegrator;
+use pbrt::integrator::sppm::SppmIntegrator;
+use pbrt::integrator::whitted::WhittedIntegrator;
+use pbrt::integrator::{Integrator, BOUNCE_CAP, RAY_COUNT};
+use pbrt::light::{
+    DirectionalLight, EnvironmentLight, Light, PointLight, SpotLight, DEFAULT_LIGHT_GROUP,
+};
 use pbrt::matrix4::Matrix4x4;
 use pbrt::point::Point;
-use pbrt::rendering::{Intersectable, Ray};
-use pbrt::scene::{Coloration, Element, Material, Plane, Polygon, Scene, Sphere, Texture};
+use pbrt::scene::{
+    Attributes, Coloration, Element, Material, Metal, Plane, Polygon, Power, Scalar, Scene, Sphere,
+    Texture, TextureCoords,
+};
+use pbrt::sky::SkyLight;
+use pbrt::spectrum::Dispersion;
 use pbrt::vector3::Vector3;
-use rand::Rng;
-use rayon::prelude::*;
 use std::path::{Path, PathBuf};
+use std::slice::Iter;
+use std::str::FromStr;
 use std::time::Instant;
 
 mod pbrt;
 
-const FLOATING_POINT_BACKOFF: f64 = 0.01;
-const RAY_COUNT: u32 = 16;
-const BOUNCE_CAP: u32 = 8;
-// RAY_COUNT + BOUNCE_CAP
-const ROUND_COUNT: u32 = 128;
-const NUM_RAYS: usize = 8;
-
 fn convert_objects_to_polygons(
     obj: &Obj<obj::SimplePolygon>,
     object_to_world: Matrix4x4,
+    attributes: Attributes,
 ) -> Vec<Element> {
     let mut polygons = vec![];
 
@@ -33,14 +42,22 @@ fn convert_objects_to_polygons(
             z: floats[2] as f64,
         };
 
-        let t = object_to_world.clone() * v;
-        t
+        object_to_world.clone() * v
+    };
+
+    // Meshes without texture coordinates map the whole triangle to the first texel.
+    let make_texture_coords = |index: Option<usize>| match index {
+        Some(index) => TextureCoords {
+            x: obj.texture[index][0],
+            y: obj.texture[index][1],
+        },
+        None => TextureCoords { x: 0.0, y: 0.0 },
     };
 
     let make_polygon = |index1, index2, index3| {
-        let obj::IndexTuple(index1, _, _) = index1;
-        let obj::IndexTuple(index2, _, _) = index2;
-        let obj::IndexTuple(index3, _, _) = index3;
+        let obj::IndexTuple(index1, texture1, _) = index1;
+        let obj::IndexTuple(index2, texture2, _) = index2;
+        let obj::IndexTuple(index3, texture3, _) = index3;
 
         let vertex1 = make_vector(&obj.position[index1]);
         let vertex2 = make_vector(&obj.position[index2]);
@@ -54,6 +71,11 @@ fn convert_objects_to_polygons(
         Element::Polygon(Polygon {
             vertices: [vertex1, vertex2, vertex3],
             normal,
+            texture_coords: [
+                make_texture_coords(texture1),
+                make_texture_coords(texture2),
+                make_texture_coords(texture3),
+            ],
             material: Material::Diffuse {
                 albedo: 0.18,
                 color: Coloration::Color(Color {
@@ -61,7 +83,9 @@ fn convert_objects_to_polygons(
                     g: 1.0,
                     b: 0.4,
                 }),
+                sigma: 0.0,
             },
+            attributes,
         })
     };
 
@@ -77,10 +101,431 @@ fn convert_objects_to_polygons(
         }
     }
 
-    return polygons;
+    polygons
+}
+
+struct Options {
+    integrator: String,
+    iterations: u32,
+    photons: usize,
+    radius: f64,
+    bootstrap: usize,
+    chains: usize,
+    mutations: usize,
+    sigma: f64,
+    large_step: f64,
+    ao_radius: f64,
+    environment: Option<PathBuf>,
+    environment_rotation: f64,
+    environment_intensity: f32,
+    sky: bool,
+    sun_elevation: f64,
+    sun_azimuth: f64,
+    turbidity: f64,
+    ground_albedo: f32,
+    sky_intensity: f32,
+    blackbody: Option<f64>,
+    point_light: bool,
+    spot_light: bool,
+    directional: Option<(Vector3, Color)>,
+    ies: Option<PathBuf>,
+    emitter_power: Option<Power>,
+    ies_power: Option<Power>,
+    metal: Option<Metal>,
+    roughness: String,
+    glass_roughness: String,
+    glass_transmittance: String,
+    glass_distance: f32,
+    dispersion: Option<Dispersion>,
+    glass_index: Option<f32>,
+    film_thickness: Option<f32>,
+    film_index: f32,
+    coat: bool,
+    coat_roughness: String,
+    coat_transmittance: String,
+    mean_free_path: Option<String>,
+    scattering_albedo: String,
+    scattering: Option<String>,
+    absorption: Option<String>,
+    diffuse_sigma: f32,
+    principled: bool,
+    base_color: String,
+    metallic: String,
+    specular: String,
+    sheen: String,
+    clearcoat: String,
+    transmission: String,
+    subsurface: String,
+    light_groups: Vec<(String, u32)>,
+    light_links: Vec<(String, u32)>,
+}
+
+/// Names the elements of the scene go by on the command line.
+const ELEMENT_NAMES: [&str; 10] = [
+    "floor",
+    "ceiling",
+    "right-wall",
+    "left-wall",
+    "back-wall",
+    "front-wall",
+    "glass-sphere",
+    "red-sphere",
+    "blue-sphere",
+    "teapot",
+];
+
+/// Names the light sources go by on the command line.
+const LIGHT_NAMES: [&str; 5] = [
+    "point-light",
+    "spot-light",
+    "directional",
+    "environment",
+    "sky",
+];
+
+fn next_value<T: FromStr>(args: &mut Iter<String>, name: &str) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("Missing value for {}", name))?;
+
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        integrator: String::from("path"),
+        iterations: RAY_COUNT,
+        photons: 50_000,
+        radius: 0.25,
+        bootstrap: 100_000,
+        chains: 1000,
+        mutations: 100,
+        sigma: 0.01,
+        large_step: 0.3,
+        ao_radius: 1.0,
+        environment: None,
+        environment_rotation: 0.0,
+        environment_intensity: 1.0,
+        sky: false,
+        sun_elevation: 45.0,
+        sun_azimuth: 0.0,
+        turbidity: 3.0,
+        ground_albedo: 0.3,
+        sky_intensity: 1.0,
+        blackbody: None,
+        point_light: false,
+        spot_light: false,
+        directional: None,
+        ies: None,
+        emitter_power: None,
+        ies_power: None,
+        metal: None,
+        roughness: String::from("0.2"),
+        glass_roughness: String::from("0"),
+        glass_transmittance: String::from("1,1,1"),
+        glass_distance: 1.0,
+        dispersion: None,
+        glass_index: None,
+        film_thickness: None,
+        film_index: 1.33,
+        coat: false,
+        coat_roughness: String::from("0"),
+        coat_transmittance: String::from("1,1,1"),
+        mean_free_path: None,
+        scattering_albedo: String::from("0.95,0.95,0.95"),
+        scattering: None,
+        absorption: None,
+        diffuse_sigma: 0.0,
+        principled: false,
+        base_color: String::from("0.8,0.8,0.8"),
+        metallic: String::from("0"),
+        specular: String::from("0.5"),
+        sheen: String::from("0"),
+        clearcoat: String::from("0"),
+        transmission: String::from("0"),
+        subsurface: String::from("0"),
+        light_groups: vec![],
+        light_links: vec![],
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--integrator" => options.integrator = next_value(&mut args, arg)?,
+            "--iterations" => options.iterations = next_value(&mut args, arg)?,
+            "--photons" => options.photons = next_value(&mut args, arg)?,
+            "--radius" => options.radius = next_value(&mut args, arg)?,
+            "--bootstrap" => options.bootstrap = next_value(&mut args, arg)?,
+            "--chains" => options.chains = next_value(&mut args, arg)?,
+            "--mutations" => options.mutations = next_value(&mut args, arg)?,
+            "--sigma" => options.sigma = next_value(&mut args, arg)?,
+            "--large-step" => options.large_step = next_value(&mut args, arg)?,
+            "--ao-radius" => options.ao_radius = next_value(&mut args, arg)?,
+            "--environment" => options.environment = Some(next_value(&mut args, arg)?),
+            "--environment-rotation" => options.environment_rotation = next_value(&mut args, arg)?,
+            "--environment-intensity" => {
+                options.environment_intensity = next_value(&mut args, arg)?
+            }
+            "--sky" => options.sky = true,
+            "--sun-elevation" => options.sun_elevation = next_value(&mut args, arg)?,
+            "--sun-azimuth" => options.sun_azimuth = next_value(&mut args, arg)?,
+            "--turbidity" => options.turbidity = next_value(&mut args, arg)?,
+            "--ground-albedo" => options.ground_albedo = next_value(&mut args, arg)?,
+            "--sky-intensity" => options.sky_intensity = next_value(&mut args, arg)?,
+            "--blackbody" => options.blackbody = Some(next_value(&mut args, arg)?),
+            "--point-light" => options.point_light = true,
+            "--spot-light" => options.spot_light = true,
+            "--directional" => {
+                let direction = parse_vector(&next_value::<String>(&mut args, arg)?)?;
+                let color = parse_color(&next_value::<String>(&mut args, arg)?)?;
+                options.directional = Some((direction, color));
+            }
+            "--ies" => options.ies = Some(next_value(&mut args, arg)?),
+            "--emitter-power" => options.emitter_power = Some(next_value(&mut args, arg)?),
+            "--ies-power" => options.ies_power = Some(next_value(&mut args, arg)?),
+            "--metal" => options.metal = Some(next_value(&mut args, arg)?),
+            "--roughness" => options.roughness = next_value(&mut args, arg)?,
+            "--glass-roughness" => options.glass_roughness = next_value(&mut args, arg)?,
+            "--glass-transmittance" => options.glass_transmittance = next_value(&mut args, arg)?,
+            "--glass-distance" => options.glass_distance = next_value(&mut args, arg)?,
+            "--dispersion" => options.dispersion = Some(next_value(&mut args, arg)?),
+            "--glass-index" => options.glass_index = Some(next_value(&mut args, arg)?),
+            "--film-thickness" => options.film_thickness = Some(next_value(&mut args, arg)?),
+            "--film-index" => options.film_index = next_value(&mut args, arg)?,
+            "--coat" => options.coat = true,
+            "--coat-roughness" => options.coat_roughness = next_value(&mut args, arg)?,
+            "--coat-transmittance" => options.coat_transmittance = next_value(&mut args, arg)?,
+            "--mean-free-path" => options.mean_free_path = Some(next_value(&mut args, arg)?),
+            "--scattering-albedo" => options.scattering_albedo = next_value(&mut args, arg)?,
+            "--scattering" => options.scattering = Some(next_value(&mut args, arg)?),
+            "--absorption" => options.absorption = Some(next_value(&mut args, arg)?),
+            "--diffuse-sigma" => options.diffuse_sigma = next_value(&mut args, arg)?,
+            "--principled" => options.principled = true,
+            "--base-color" => options.base_color = next_value(&mut args, arg)?,
+            "--metallic" => options.metallic = next_value(&mut args, arg)?,
+            "--specular" => options.specular = next_value(&mut args, arg)?,
+            "--sheen" => options.sheen = next_value(&mut args, arg)?,
+            "--clearcoat" => options.clearcoat = next_value(&mut args, arg)?,
+            "--transmission" => options.transmission = next_value(&mut args, arg)?,
+            "--subsurface" => options.subsurface = next_value(&mut args, arg)?,
+            "--light-group" => {
+                let name: String = next_value(&mut args, arg)?;
+                if !ELEMENT_NAMES.contains(&name.as_str()) && !LIGHT_NAMES.contains(&name.as_str())
+                {
+                    return Err(format!("Unknown element or light: {}", name));
+                }
+                let groups = parse_groups(&next_value::<String>(&mut args, arg)?)?;
+                options.light_groups.push((name, groups));
+            }
+            "--light-links" => {
+                let name: String = next_value(&mut args, arg)?;
+                if !ELEMENT_NAMES.contains(&name.as_str()) {
+                    return Err(format!("Unknown element: {}", name));
+                }
+                let groups = parse_groups(&next_value::<String>(&mut args, arg)?)?;
+                options.light_links.push((name, groups));
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn create_integrator(options: &Options) -> Result<Box<dyn Integrator>, String> {
+    let max_depth = BOUNCE_CAP as usize;
+
+    match options.integrator.as_str() {
+        "path" => Ok(Box::new(PathIntegrator { spectral: false })),
+        "spectral" => Ok(Box::new(PathIntegrator { spectral: true })),
+        "bdpt" => Ok(Box::new(BdptIntegrator::new(max_depth))),
+        "sppm" => Ok(Box::new(SppmIntegrator::new(
+            options.iterations,
+            options.photons,
+            options.radius,
+            max_depth,
+        ))),
+        "mlt" => Ok(Box::new(MltIntegrator {
+            max_depth,
+            bootstrap_samples: options.bootstrap,
+            chains: options.chains,
+            mutations_per_pixel: options.mutations,
+            sigma: options.sigma,
+            large_step_probability: options.large_step,
+        })),
+        "normals" => Ok(Box::new(DebugIntegrator::new(DebugChannel::Normals))),
+        "depth" => Ok(Box::new(DebugIntegrator::new(DebugChannel::Depth))),
+        "uv" => Ok(Box::new(DebugIntegrator::new(DebugChannel::TextureCoords))),
+        "albedo" => Ok(Box::new(DebugIntegrator::new(DebugChannel::Albedo))),
+        "id" => Ok(Box::new(DebugIntegrator::new(DebugChannel::ElementId))),
+        "ao" => Ok(Box::new(DebugIntegrator::new(
+            DebugChannel::AmbientOcclusion {
+                radius: options.ao_radius,
+                samples: RAY_COUNT,
+            },
+        ))),
+        "whitted" => Ok(Box::new(WhittedIntegrator::new(max_depth))),
+        _ => Err(format!("Unknown integrator: {}", options.integrator)),
+    }
+}
+
+/// A number, or the path of a texture to read the parameter from.
+fn load_scalar(value: &str) -> Result<Scalar, String> {
+    match value.parse() {
+        Ok(value) => Ok(Scalar::Value(value)),
+        Err(_) => Texture::load_texture(PathBuf::from(value))
+            .map(|texture| Scalar::Texture(Box::new(texture))),
+    }
+}
+
+/// A scalar as loaded by `load_scalar`, which must lie in `[0, 1]`. Textures always do.
+fn load_weight(name: &str, value: &str) -> Result<Scalar, String> {
+    match load_scalar(value)? {
+        Scalar::Value(weight) if !(0.0..=1.0).contains(&weight) => {
+            Err(format!("{} must be between 0 and 1: {}", name, weight))
+        }
+        scalar => Ok(scalar),
+    }
+}
+
+/// Linear `r,g,b` components.
+fn parse_color(value: &str) -> Result<Color, String> {
+    let components: Result<Vec<f32>, _> = value.split(',').map(str::parse).collect();
+
+    match components {
+        Ok(ref rgb) if rgb.len() == 3 => Ok(Color {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+        }),
+        _ => Err(format!("Invalid color: {}", value)),
+    }
+}
+
+/// `x,y,z` components.
+fn parse_vector(value: &str) -> Result<Vector3, String> {
+    let components: Result<Vec<f64>, _> = value.split(',').map(str::parse).collect();
+
+    match components {
+        Ok(ref xyz) if xyz.len() == 3 => Ok(Vector3 {
+            x: xyz[0],
+            y: xyz[1],
+            z: xyz[2],
+        }),
+        _ => Err(format!("Invalid vector: {}", value)),
+    }
+}
+
+/// Comma separated light groups, numbered from 1 to 32, as a mask with one bit per group.
+fn parse_groups(value: &str) -> Result<u32, String> {
+    value
+        .split(',')
+        .try_fold(0, |mask, group| match group.parse::<u32>() {
+            Ok(group) if (1..=32).contains(&group) => Ok(mask | 1 << (group - 1)),
+            _ => Err(format!("Invalid light group: {}", group)),
+        })
+}
+
+/// Light groups the element or light called `name` was put in, the default one unless given.
+fn light_group(options: &Options, name: &str) -> u32 {
+    options
+        .light_groups
+        .iter()
+        .rev()
+        .find(|(n, _)| n == name)
+        .map_or(DEFAULT_LIGHT_GROUP, |&(_, groups)| groups)
+}
+
+/// Attributes of the element called `name`, lit by every light unless linked to some groups.
+fn attributes(options: &Options, name: &str) -> Attributes {
+    let mut attributes = Attributes {
+        light_group: light_group(options, name),
+        ..Attributes::default()
+    };
+    if let Some(&(_, links)) = options.light_links.iter().rev().find(|(n, _)| n == name) {
+        attributes.light_links = links;
+    }
+    attributes
+}
+
+/// A color as parsed by `parse_color`, or the path of a texture.
+fn load_coloration(value: &str) -> Result<Coloration, String> {
+    match parse_color(value) {
+        Ok(color) => Ok(Coloration::Color(color)),
+        Err(_) => Texture::load_texture(PathBuf::from(value)).map(Coloration::Texture),
+    }
+}
+
+/// Translucent material for the glass sphere, from either its mean free path and albedo or its
+/// scattering and absorption coefficients. `None` when neither is given.
+fn subsurface_material(options: &Options) -> Result<Option<Material>, String> {
+    let index = options.glass_index.unwrap_or(1.4);
+
+    match (
+        &options.mean_free_path,
+        &options.scattering,
+        &options.absorption,
+    ) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => Err(String::from(
+            "--mean-free-path can't be combined with --scattering or --absorption",
+        )),
+        (Some(mean_free_path), None, None) => Ok(Some(Material::Subsurface {
+            index,
+            albedo: parse_color(&options.scattering_albedo)?,
+            mean_free_path: parse_color(mean_free_path)?,
+        })),
+        (None, Some(scattering), Some(absorption)) => Ok(Some(Material::subsurface(
+            index,
+            parse_color(scattering)?,
+            parse_color(absorption)?,
+        ))),
+        (None, None, None) => Ok(None),
+        _ => Err(String::from(
+            "--scattering and --absorption must be given together",
+        )),
+    }
+}
+
+/// Varnish over `base`, tinted to let `--coat-transmittance` of the light through at normal
+/// incidence.
+fn coated_material(options: &Options, base: Material) -> Result<Material, String> {
+    let thickness = 0.01;
+
+    Ok(Material::Coated {
+        base: Box::new(base),
+        index: 1.5,
+        roughness: load_scalar(&options.coat_roughness)?,
+        absorption: Material::absorption_for(parse_color(&options.coat_transmittance)?, thickness),
+        thickness,
+    })
+}
+
+/// Principled material with every parameter taken from the options.
+fn principled_material(options: &Options) -> Result<Material, String> {
+    Ok(Material::Principled {
+        base_color: load_coloration(&options.base_color)?,
+        metallic: load_weight("--metallic", &options.metallic)?,
+        roughness: load_weight("--roughness", &options.roughness)?,
+        specular: load_weight("--specular", &options.specular)?,
+        sheen: load_weight("--sheen", &options.sheen)?,
+        clearcoat: load_weight("--clearcoat", &options.clearcoat)?,
+        transmission: load_weight("--transmission", &options.transmission)?,
+        subsurface: load_weight("--subsurface", &options.subsurface)?,
+    })
+}
+
+fn fail(error: String) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1);
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = parse_options(&args).unwrap_or_else(|error| fail(error));
+    let integrator = create_integrator(&options).unwrap_or_else(|error| fail(error));
+
     let load_start = Instant::now();
 
     let mesh_path = Path::new("teapot.obj");
@@ -90,7 +535,11 @@ fn main() {
         * Matrix4x4::scale_linear(1.0)
         * Matrix4x4::translate(1.0, -1.575, 0.0);
 
-    let teapot_1_polygons = convert_objects_to_polygons(&mesh, object_to_world_matrix);
+    let teapot_1_polygons = convert_objects_to_polygons(
+        &mesh,
+        object_to_world_matrix,
+        attributes(&options, "teapot"),
+    );
 
     // let green_mat = Material::Diffuse {
     //   albedo: 0.18,
@@ -103,18 +552,57 @@ fn main() {
 
     let red_mat = Material::Emissive {
         intensity: 200.0,
-        emission: Color {
+        two_sided: false,
+        spectrum: None,
+        emission: Coloration::Color(Color {
             r: 1.0,
             g: 0.0,
             b: 0.0,
-        },
+        }),
     };
 
-    let transparent_mat = Material::Refractive { index: 1.5 };
-
-    let blue_mat = Material::Reflective;
+    // Thin film coating the glass and metal spheres, in nanometers.
+    let film = options.film_thickness.map(|thickness| ThinFilm {
+        thickness,
+        index: options.film_index,
+    });
+
+    let transparent_mat = Material::Refractive {
+        index: options.glass_index.unwrap_or_else(|| {
+            options
+                .dispersion
+                .map_or(1.5, |dispersion| dispersion.reference_index() as f32)
+        }),
+        roughness: load_scalar(&options.glass_roughness).unwrap_or_else(|error| fail(error)),
+        absorption: Material::absorption_for(
+            parse_color(&options.glass_transmittance).unwrap_or_else(|error| fail(error)),
+            options.glass_distance,
+        ),
+        dispersion: options.dispersion,
+        film,
+    };
+    let transparent_mat = subsurface_material(&options)
+        .unwrap_or_else(|error| fail(error))
+        .unwrap_or(transparent_mat);
+
+    let blue_mat = match options.metal {
+        Some(metal) => Material::conductor(
+            metal,
+            load_scalar(&options.roughness).unwrap_or_else(|error| fail(error)),
+            film,
+        ),
+        None if options.principled => {
+            principled_material(&options).unwrap_or_else(|error| fail(error))
+        }
+        None => Material::Reflective,
+    };
+    let blue_mat = if options.coat {
+        coated_material(&options, blue_mat).unwrap_or_else(|error| fail(error))
+    } else {
+        blue_mat
+    };
 
-    let entities = vec![
+    let mut entities = vec![
         // floor
         Element::Plane(Plane {
             origin: Point::new(0.0, -3.0, -5.0),
@@ -124,18 +612,25 @@ fn main() {
                 color: Coloration::Texture(
                     Texture::load_texture(PathBuf::from("./checkerboard.png")).unwrap(),
                 ),
+                sigma: options.diffuse_sigma,
             },
+            attributes: attributes(&options, "floor"),
         }),
         // ceiling
         Element::Plane(Plane {
             origin: Point::new(0.0, 5.0, 5.0),
             normal: Vector3::up(),
-            material: Material::Emissive {
-                intensity: 200.0,
-                emission: Color {
-                    r: 1.0,
-                    g: 1.0,
-                    b: 1.0,
+            material: match options.blackbody {
+                Some(kelvin) => Material::blackbody(kelvin, 200.0, false),
+                None => Material::Emissive {
+                    intensity: 200.0,
+                    two_sided: false,
+                    spectrum: None,
+                    emission: Coloration::Color(Color {
+                        r: 1.0,
+                        g: 1.0,
+                        b: 1.0,
+                    }),
                 },
             },
             // material: Material::Diffuse {
@@ -146,6 +641,7 @@ fn main() {
             //     b: 1.0,
             //   }),
             // },
+            attributes: attributes(&options, "ceiling"),
         }),
         // right wall
         Element::Plane(Plane {
@@ -158,7 +654,9 @@ fn main() {
                     g: 1.0,
                     b: 1.0,
                 }),
+                sigma: options.diffuse_sigma,
             },
+            attributes: attributes(&options, "right-wall"),
         }),
         // left wall
         Element::Plane(Plane {
@@ -171,7 +669,9 @@ fn main() {
                     g: 1.0,
                     b: 1.0,
                 }),
+                sigma: options.diffuse_sigma,
             },
+            attributes: attributes(&options, "left-wall"),
         }),
         // back wall
         Element::Plane(Plane {
@@ -184,7 +684,9 @@ fn main() {
                     g: 1.0,
                     b: 1.0,
                 }),
+                sigma: options.diffuse_sigma,
             },
+            attributes: attributes(&options, "back-wall"),
         }),
         // front wall
         Element::Plane(Plane {
@@ -197,7 +699,9 @@ fn main() {
                     g: 1.0,
                     b: 1.0,
                 }),
+                sigma: options.diffuse_sigma,
             },
+            attributes: attributes(&options, "front-wall"),
         }),
         // Element::Sphere(Sphere {
         //   center: Point::new(0.0, 0.0, -5.0),
@@ -208,259 +712,128 @@ fn main() {
             center: Point::new(-3.0, 1.0, -6.0),
             radius: 2.0,
             material: transparent_mat,
+            attributes: attributes(&options, "glass-sphere"),
         }),
         Element::Sphere(Sphere {
             center: Point::new(-2.0, -2.0, -6.0),
             radius: 1.0,
             material: red_mat,
+            attributes: attributes(&options, "red-sphere"),
         }),
         Element::Sphere(Sphere {
             center: Point::new(3.0, 0.0, -10.0),
             radius: 2.0,
             material: blue_mat,
+            attributes: attributes(&options, "blue-sphere"),
         }),
     ];
 
-    let scene = Scene {
-        width: 100,
-        height: 100,
-        fov: 90.0,
-        entities: entities
-            .into_iter()
-            .chain(teapot_1_polygons.into_iter())
-            .collect(),
-    };
-
-    let load_time = load_start.elapsed();
-    println!("Load time: {:?}", load_time);
-    let render_start = Instant::now();
-
-    render(&scene).save("test.png").unwrap();
-
-    let render_time = render_start.elapsed();
-    println!("Total time: {:?}", render_time);
-}
-
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
-    let i_dot_n = incident.dot(&normal);
-    let mut eta_i = 1.0;
-    let mut eta_t = index as f64;
-    if i_dot_n > 0.0 {
-        eta_i = eta_t;
-        eta_t = 1.0;
-    }
-
-    let sin_t = eta_i / eta_t * (1.0 - i_dot_n * i_dot_n).max(0.0).sqrt();
-    if sin_t > 1.0 {
-        //Total internal reflection
-        return 1.0;
-    } else {
-        let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
-        let cos_i = cos_t.abs();
-        let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
-        let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
-        return (r_s * r_s + r_p * r_p) / 2.0;
-    }
-}
-
-fn create_scatter_direction(normal: &Vector3) -> (Vector3, f32) {
-    let mut rng = rand::thread_rng();
-    let r1: f64 = rng.gen();
-    let r2: f64 = rng.gen();
-
-    let y = r1;
-    let azimuth = r2 * 2.0 * std::f64::consts::PI;
-    let sin_elevation = (1.0 - y * y).sqrt();
-    let x = sin_elevation * (azimuth).cos();
-    let z = sin_elevation * (azimuth).sin();
-
-    let hemisphere_vec = Vector3 { x, y, z };
-
-    let (n_t, n_b) = create_coordinate_system(normal);
-
-    let scatter = Vector3 {
-        x: hemisphere_vec.x * n_b.x + hemisphere_vec.y * normal.x + hemisphere_vec.z * n_t.x,
-        y: hemisphere_vec.x * n_b.y + hemisphere_vec.y * normal.y + hemisphere_vec.z * n_t.y,
-        z: hemisphere_vec.x * n_b.z + hemisphere_vec.y * normal.z + hemisphere_vec.z * n_t.z,
-    };
-
-    let weight = (1.0 / scatter.dot(normal)) as f32;
-
-    (scatter, weight)
-}
-
-fn create_coordinate_system(normal: &Vector3) -> (Vector3, Vector3) {
-    let n_t = if (normal.x.abs()) > (normal.y.abs()) {
-        Vector3 {
-            x: normal.z,
-            y: 0.0,
-            z: -normal.x,
-        }
-        .normalize()
-    } else {
-        Vector3 {
-            x: 0.0,
-            y: -normal.z,
-            z: normal.y,
-        }
-        .normalize()
-    };
-    let n_b = normal.cross(&n_t);
-
-    (n_t, n_b)
-}
-
-fn make_reflection(incident: Vector3, normal: Vector3) -> Vector3 {
-    incident - normal * (2.0 * incident.dot(&normal))
-}
-
-fn get_color(scene: &Scene, x: u32, y: u32) -> Color {
-    let mut color_acc = Color::black();
-
-    let mut rays = vec![];
-    let mut masks = vec![];
-
-    rays.push(Ray::create_prime(x, y, scene));
-    masks.push(Color::white());
-
-    let mut bounce_i = 0;
-    while bounce_i < BOUNCE_CAP {
-        let mut ray_i = (rays.len() - 1) as i32;
-        while ray_i >= 0 {
-            let ray_u = ray_i as usize;
-            let mut ray = *rays.get_mut(ray_u).unwrap();
-            let mut color_mask = *masks.get_mut(ray_u).unwrap();
-
-            if let Some(intersection) = scene.trace(&ray) {
-                let hit_point = ray.origin + (ray.direction * intersection.distance);
-                let surface_normal = intersection.element.surface_normal(&hit_point);
-
-                ray.origin = hit_point + (surface_normal * FLOATING_POINT_BACKOFF);
-                let material = intersection.element.material().clone();
-
-                match material {
-                    Material::Diffuse { color, albedo } => {
-                        let texture_coords = intersection.element.texture_coords(&hit_point);
-
-                        let (direction, weight) = create_scatter_direction(&surface_normal);
-                        ray.direction = direction;
-
-                        let cosine_angle = direction.dot(&surface_normal) as f32;
-                        let reflected_power = albedo * std::f32::consts::PI;
-                        let reflected_color =
-                            color.color(&texture_coords) * cosine_angle * reflected_power * weight;
-
-                        color_mask = color_mask * reflected_color;
-                    }
-
-                    Material::Emissive {
-                        emission,
-                        intensity,
-                    } => {
-                        let (direction, _) = create_scatter_direction(&surface_normal);
-                        ray.direction = direction;
-                        color_acc = color_acc + (*emission * color_mask * *intensity);
-                    }
-
-                    Material::Reflective => {
-                        ray.direction = make_reflection(ray.direction, surface_normal);
-                    }
-
-                    Material::Refractive { index } => {
-                        let kr = fresnel(ray.direction, surface_normal, *index) as f32;
-
-                        if kr < 1.0 {
-                            if rays.len() < NUM_RAYS {
-                                rays.push(
-                                    Ray::create_transmission(
-                                        surface_normal,
-                                        ray.direction,
-                                        hit_point,
-                                        FLOATING_POINT_BACKOFF,
-                                        *index,
-                                    )
-                                    .unwrap(),
-                                );
-                                masks.push(color_mask * (1.0 - kr));
-                            }
-                        }
-
-                        ray.direction = make_reflection(ray.direction, surface_normal);
-
-                        color_mask = color_mask * kr
-                    }
-                }
-            } else {
-                print!("Resseting to black: {:?}\n", color_mask);
-                color_mask = Color::black();
+    if let Some(power) = options.emitter_power {
+        // Only bounded emitters have a total power, the emissive ceiling keeps its intensity.
+        for element in entities.iter_mut() {
+            if element.material().is_emissive() && element.area().is_some() {
+                element.set_power(power).unwrap_or_else(|error| fail(error));
             }
-
-            rays[ray_u] = ray;
-            masks[ray_u] = color_mask;
-
-            ray_i -= 1;
         }
-        bounce_i += 1;
     }
 
-    return color_acc;
-}
-
-fn render_pixel(scene: &Scene, x: &u32, y: &u32) -> Vec<u8> {
-    let mut ray_num = 0;
-    let mut color_acc = Color::black();
-
-    while ray_num < RAY_COUNT {
-        color_acc = color_acc + get_color(scene, *x, *y) * (1.0 / (RAY_COUNT * ROUND_COUNT) as f32);
-        ray_num += 1;
+    let ies_profile = options.ies.as_ref().map(|path| {
+        let mut profile = IesProfile::load(path.clone()).unwrap_or_else(|error| fail(error));
+        if let Some(power) = options.ies_power {
+            profile.set_power(power).unwrap_or_else(|error| fail(error));
+        }
+        profile
+    });
+
+    let mut lights: Vec<Box<dyn Light>> = vec![];
+
+    // A photometric profile is always given to the point light, and sets its candela.
+    if options.point_light || ies_profile.is_some() {
+        lights.push(Box::new(PointLight {
+            position: Point::new(0.0, 4.0, -5.0),
+            color: Color::white(),
+            intensity: if ies_profile.is_some() { 1.0 } else { 10000.0 },
+            profile: ies_profile,
+            light_group: light_group(&options, "point-light"),
+        }));
     }
 
-    color_acc.clamp().to_rgba().data.to_vec()
-}
+    if options.spot_light {
+        lights.push(Box::new(SpotLight {
+            position: Point::new(3.0, 4.0, -4.0),
+            direction: Point::new(2.0, -3.0, -6.0) - Point::new(3.0, 4.0, -4.0),
+            color: Color {
+                r: 1.0,
+                g: 0.9,
+                b: 0.7,
+            },
+            intensity: 10000.0,
+            cone_angle: 20.0,
+            falloff_angle: 15.0,
+            profile: None,
+            light_group: light_group(&options, "spot-light"),
+        }));
+    }
 
-fn render(scene: &Scene) -> ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-    let height = scene.height;
-    let width = scene.width;
-    // let mut buffer = vec![vec![(0, 0); width]; height];
-
-    // for x in 0..width {
-    //     for y in 0..height {
-    //         buffer[y][x] = (x as u32, y as u32);
-    //     }
-    // }
-
-    // let source: Vec<u8> = buffer
-    //     .clone()
-    //     .par_iter()
-    //     .flat_map(|vec| vec)
-    //     .flat_map(|(x, y)| render_pixel(&scene, &x, &y))
-    //     .collect();
-
-    let mut buffer = Vec::new();
-    for y in 0..height {
-        for x in 0..width {
-            let color = render_pixel(&scene, &x, &y);
-            for b in color {
-                buffer.push(b);
-            }
-        }
+    if let Some((direction, color)) = options.directional {
+        lights.push(Box::new(DirectionalLight {
+            direction,
+            color,
+            intensity: 1.0,
+            light_group: light_group(&options, "directional"),
+        }));
     }
 
-    ImageBuffer::from_vec(scene.width, scene.height, buffer).unwrap()
+    if let Some(ref path) = options.environment {
+        let mut environment = EnvironmentLight::load(
+            path.clone(),
+            options.environment_rotation,
+            options.environment_intensity,
+        )
+        .unwrap_or_else(|error| fail(error));
+        environment.light_group = light_group(&options, "environment");
+        lights.push(Box::new(environment));
+    }
 
-    // let mut buffer = ImageBuffer::new(scene.width, scene.height);
+    if options.sky {
+        // Azimuth is measured from the view direction towards the right.
+        let (elevation, azimuth) = (
+            options.sun_elevation.to_radians(),
+            options.sun_azimuth.to_radians(),
+        );
+        let mut sky = SkyLight::new(
+            Vector3 {
+                x: elevation.cos() * azimuth.sin(),
+                y: elevation.sin(),
+                z: -elevation.cos() * azimuth.cos(),
+            },
+            options.turbidity,
+            Color::white() * options.ground_albedo,
+            options.sky_intensity,
+        );
+        sky.light_group = light_group(&options, "sky");
+        lights.push(Box::new(sky.sun()));
+        lights.push(Box::new(sky));
+    }
 
-    // ImageBuffer::from_fn(scene.width, scene.height, |x, y| {
+    let scene = Scene {
+        width: 100,
+        height: 100,
+        fov: 90.0,
+        entities: entities.into_iter().chain(teapot_1_polygons).collect(),
+        lights,
+    };
 
-    //     // let mut ray_num = 0;
-    //     // let mut color_acc = Color::black();
+    let load_time = load_start.elapsed();
+    println!("Load time: {:?}", load_time);
+    let render_start = Instant::now();
 
-    //     // while ray_num < RAY_COUNT {
-    //     //     color_acc =
-    //     //         color_acc + get_color(scene, x, y) * (1.0 / (RAY_COUNT * ROUND_COUNT) as f32);
-    //     //     ray_num += 1;
-    //     // }
+    integrator
+        .render(&scene)
+        .to_image()
+        .save("test.png")
+        .unwrap();
 
-    //     // color_acc.clamp().to_rgba()
-    // })
+    let render_time = render_start.elapsed();
+    println!("Total time: {:?}", render_time);
 }
@@ -0,0 +1,1422 @@
+use super::color::Color;
+use super::microfacet::{reflection_half_vector, upper, TrowbridgeReitz};
+use super::rendering::{make_reflection, refract};
+use super::sampler::{HashSampler, Sampler};
+use super::sampling::{cosine_hemisphere_pdf, cosine_sample_hemisphere, create_coordinate_system};
+use super::spectrum::{SampledWavelengths, Spectrum};
+use super::vector3::Vector3;
+
+/// Direction picked by a BSDF together with its value and density.
+pub struct BsdfSample {
+    pub wi: Vector3,
+    pub f: Color,
+    /// Solid angle density, or the probability of the chosen lobe for specular samples.
+    pub pdf: f64,
+    pub specular: bool,
+    /// Whether `pdf` is only proportional to the density of `wi`, for stochastic BSDFs. `pdf`
+    /// must then be asked for the actual density.
+    pub pdf_is_proportional: bool,
+}
+
+/// Scattering function of a surface in its local shading frame, where `y` is the normal. Both
+/// directions point away from the surface.
+pub trait Bsdf {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color;
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64;
+
+    /// Whether the BSDF only scatters into discrete directions, so `f` and `pdf` are always zero.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Index of refraction below the surface relative to above it, for BSDFs transmitting light.
+    fn eta(&self) -> f64 {
+        1.0
+    }
+}
+
+/// What a path carries, which decides how refraction scales it. Camera paths gather radiance,
+/// paths leaving the lights carry importance, which isn't compressed into a denser medium.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportMode {
+    Radiance,
+    Importance,
+}
+
+/// Factor scaling the radiance refracted towards `wo`, at `cos_o` from the normal, through a
+/// boundary with relative index of refraction `eta`: light gets compressed into the smaller solid
+/// angle of the denser side.
+pub fn refracted_radiance_scale(cos_o: f64, eta: f64) -> f32 {
+    let eta = if cos_o > 0.0 { eta } else { 1.0 / eta };
+
+    (1.0 / (eta * eta)) as f32
+}
+
+/// Orthonormal basis around a shading normal, matching `sampling::to_world`.
+pub struct Frame {
+    pub normal: Vector3,
+    tangent: Vector3,
+    bitangent: Vector3,
+}
+
+impl Frame {
+    pub fn new(normal: Vector3) -> Frame {
+        let (tangent, bitangent) = create_coordinate_system(&normal);
+
+        Frame {
+            normal,
+            tangent,
+            bitangent,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vector3) -> Vector3 {
+        Vector3 {
+            x: v.dot(&self.bitangent),
+            y: v.dot(&self.normal),
+            z: v.dot(&self.tangent),
+        }
+    }
+
+    pub fn to_world(&self, v: &Vector3) -> Vector3 {
+        self.bitangent * v.x + self.normal * v.y + self.tangent * v.z
+    }
+}
+
+/// A BSDF placed on a surface, taking and returning world space directions.
+pub struct SurfaceBsdf {
+    pub frame: Frame,
+    bsdf: Box<dyn Bsdf>,
+    /// Wavelengths of a spectral path, which its values are upsampled to. RGB when `None`.
+    pub wavelengths: Option<SampledWavelengths>,
+    pub mode: TransportMode,
+}
+
+impl SurfaceBsdf {
+    pub fn new(normal: Vector3, bsdf: Box<dyn Bsdf>) -> SurfaceBsdf {
+        SurfaceBsdf {
+            frame: Frame::new(normal),
+            bsdf,
+            wavelengths: None,
+            mode: TransportMode::Radiance,
+        }
+    }
+
+    pub fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+
+        self.spectral(self.bsdf.f(&wo, &wi) * self.transport_scale(&wo, &wi))
+    }
+
+    pub fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let wo = self.frame.to_local(wo);
+        let mut sample = self.bsdf.sample_f(&wo, sampler)?;
+        if sample.pdf == 0.0 || sample.f.is_black() {
+            return None;
+        }
+
+        sample.f = self.spectral(sample.f * self.transport_scale(&wo, &sample.wi));
+        sample.wi = self.frame.to_world(&sample.wi);
+        Some(sample)
+    }
+
+    // Scaling of the light refracted from `wi` to `wo`, in the local frame.
+    fn transport_scale(&self, wo: &Vector3, wi: &Vector3) -> f32 {
+        if self.mode == TransportMode::Radiance && !same_hemisphere(wo, wi) {
+            refracted_radiance_scale(wo.y, self.bsdf.eta())
+        } else {
+            1.0
+        }
+    }
+
+    fn spectral(&self, f: Color) -> Color {
+        match self.wavelengths {
+            Some(ref wavelengths) => wavelengths.reflectance(&f),
+            None => f,
+        }
+    }
+
+    /// Light arriving with the RGB `radiance`, in the same terms as the values of the BSDF. See
+    /// `SampledWavelengths::emission` for `spectrum`.
+    pub fn incident(&self, radiance: &Color, spectrum: Option<&Spectrum>) -> Color {
+        match self.wavelengths {
+            Some(ref wavelengths) => wavelengths.emission(radiance, spectrum),
+            None => *radiance,
+        }
+    }
+
+    pub fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        self.bsdf
+            .pdf(&self.frame.to_local(wo), &self.frame.to_local(wi))
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.bsdf.is_specular()
+    }
+}
+
+pub fn same_hemisphere(a: &Vector3, b: &Vector3) -> bool {
+    a.y * b.y > 0.0
+}
+
+/// Cosine distributed direction on the side of `wo`.
+pub fn sample_cosine_lobe(wo: &Vector3, u: (f64, f64)) -> Vector3 {
+    let mut wi = cosine_sample_hemisphere(u);
+    if wo.y < 0.0 {
+        wi.y = -wi.y;
+    }
+    wi
+}
+
+// Delta lobe chosen with probability `pdf` carrying the same fraction of energy.
+fn specular_sample(wi: Vector3, pdf: f64) -> BsdfSample {
+    tinted_specular_sample(wi, Color::white() * pdf as f32, pdf)
+}
+
+// Delta lobe chosen with probability `pdf` carrying `fraction` of the energy per channel.
+fn tinted_specular_sample(wi: Vector3, fraction: Color, pdf: f64) -> BsdfSample {
+    BsdfSample {
+        f: fraction * (1.0 / wi.y.abs()) as f32,
+        wi,
+        pdf,
+        specular: true,
+        pdf_is_proportional: false,
+    }
+}
+
+fn average(color: &Color) -> f64 {
+    (color.r as f64 + color.g as f64 + color.b as f64) / 3.0
+}
+
+/// Lambertian reflection, `color` being the reflectance over pi.
+pub struct Lambertian {
+    pub color: Color,
+}
+
+impl Bsdf for Lambertian {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        if !same_hemisphere(wo, wi) {
+            return Color::black();
+        }
+
+        self.color
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let wi = sample_cosine_lobe(wo, sampler.get_2d());
+
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi,
+            specular: false,
+            pdf_is_proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        cosine_hemisphere_pdf(wi.y.abs())
+    }
+}
+
+/// Rough diffuse reflection of Oren and Nayar, with V shaped microfacets whose slopes have a
+/// standard deviation of `sigma`. Brighter than Lambertian towards the light at grazing angles.
+pub struct OrenNayar {
+    /// Reflectance over pi.
+    pub color: Color,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    /// Surface with `sigma` in degrees.
+    pub fn new(color: Color, sigma: f32) -> OrenNayar {
+        let sigma = (sigma as f64).to_radians();
+        let sigma2 = sigma * sigma;
+
+        OrenNayar {
+            color,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Bsdf for OrenNayar {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        if !same_hemisphere(wo, wi) {
+            return Color::black();
+        }
+
+        let sin_theta = |w: &Vector3| (1.0 - w.y * w.y).max(0.0).sqrt();
+        let (sin_i, sin_o) = (sin_theta(wi), sin_theta(wo));
+
+        // Cosine of the azimuthal angle between the two directions.
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.z * wo.z) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+
+        let (sin_alpha, tan_beta) = if wi.y.abs() > wo.y.abs() {
+            (sin_o, sin_i / wi.y.abs())
+        } else {
+            (sin_i, sin_o / wo.y.abs())
+        };
+
+        self.color * (self.a + self.b * max_cos * sin_alpha * tan_beta) as f32
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let wi = sample_cosine_lobe(wo, sampler.get_2d());
+
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi,
+            specular: false,
+            pdf_is_proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        cosine_hemisphere_pdf(wi.y.abs())
+    }
+}
+
+/// Perfect mirror.
+pub struct SpecularReflection;
+
+impl Bsdf for SpecularReflection {
+    fn f(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::black()
+    }
+
+    fn sample_f(&self, wo: &Vector3, _sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        Some(specular_sample(make_reflection(-*wo, Vector3::up()), 1.0))
+    }
+
+    fn pdf(&self, _wo: &Vector3, _wi: &Vector3) -> f64 {
+        0.0
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+/// Reflectance of a conductor with complex index of refraction `eta + i k`, per channel, for
+/// light arriving at `cos_theta` from the normal.
+pub fn fresnel_conductor(cos_theta: f64, eta: &Color, k: &Color) -> Color {
+    let channel = |eta: f32, k: f32| {
+        let cos_i = cos_theta.clamp(0.0, 1.0);
+        let sin2_i = 1.0 - cos_i * cos_i;
+        let eta = Complex::new(eta as f64, k as f64);
+
+        let sin2_t = Complex::new(sin2_i, 0.0) / (eta * eta);
+        let cos_t = (Complex::new(1.0, 0.0) - sin2_t).sqrt();
+        let cos_i = Complex::new(cos_i, 0.0);
+
+        let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        ((r_parallel.norm() + r_perpendicular.norm()) * 0.5) as f32
+    };
+
+    Color {
+        r: channel(eta.r, k.r),
+        g: channel(eta.g, k.g),
+        b: channel(eta.b, k.b),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    // Squared magnitude.
+    fn norm(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // Principal square root.
+    fn sqrt(&self) -> Complex {
+        let magnitude = self.norm().sqrt();
+        if magnitude == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+
+        let re = ((magnitude + self.re) * 0.5).max(0.0).sqrt();
+        let im = ((magnitude - self.re) * 0.5).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    // `e^(i z)`, damped by the imaginary part of `z`.
+    fn exp_i(&self) -> Complex {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let scale = 1.0 / other.norm();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) * scale,
+            (self.im * other.re - self.re * other.im) * scale,
+        )
+    }
+}
+
+// Wavelengths in nanometers standing for the red, green and blue channels.
+const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+/// Transparent film of `thickness` nanometers and index of refraction `index` coating a
+/// surface, like soap or oil. Light bouncing between its two boundaries interferes with itself,
+/// tinting the reflection with colors depending on the angle of view.
+#[derive(Clone, Copy, Debug)]
+pub struct ThinFilm {
+    pub thickness: f32,
+    pub index: f32,
+}
+
+impl ThinFilm {
+    /// Reflectance of a conductor with complex index of refraction `eta + i k` under the film,
+    /// per channel.
+    pub fn conductor(&self, cos_theta: f64, eta: &Color, k: &Color) -> Color {
+        let channel = |i: usize, eta: f32, k: f32| {
+            self.reflectance(cos_theta, 1.0, Complex::new(eta as f64, k as f64), i)
+        };
+
+        Color {
+            r: channel(0, eta.r, k.r),
+            g: channel(1, eta.g, k.g),
+            b: channel(2, eta.b, k.b),
+        }
+    }
+
+    /// Reflectance of the boundary of a dielectric with index of refraction `eta` and the film
+    /// on its outside, per channel. A negative `cos_theta` means light arriving from inside.
+    pub fn dielectric(&self, cos_theta: f64, eta: f64) -> Color {
+        let (outer, substrate) = if cos_theta < 0.0 {
+            (eta, 1.0)
+        } else {
+            (1.0, eta)
+        };
+        let channel =
+            |i: usize| self.reflectance(cos_theta.abs(), outer, Complex::new(substrate, 0.0), i);
+
+        Color {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
+        }
+    }
+
+    // Airy summation of the waves reflected by both boundaries of the film, for light coming
+    // from a medium of index `outer` at `cos_theta` onto `substrate`, at the wavelength of
+    // `channel`. Averages both polarizations.
+    fn reflectance(&self, cos_theta: f64, outer: f64, substrate: Complex, channel: usize) -> f32 {
+        let one = Complex::new(1.0, 0.0);
+        let cos0 = Complex::new(cos_theta.clamp(0.0, 1.0), 0.0);
+        let sin2 = 1.0 - cos_theta * cos_theta;
+        let n0 = Complex::new(outer, 0.0);
+        let n1 = Complex::new(self.index as f64, 0.0);
+        let n2 = substrate;
+
+        // Snell's law through complex cosines, covering total internal reflection and metals.
+        let cos_in = |n: Complex| (one - Complex::new(outer * outer * sin2, 0.0) / (n * n)).sqrt();
+        let cos1 = cos_in(n1);
+        let cos2 = cos_in(n2);
+
+        let perpendicular = |ni: Complex, ci: Complex, nj: Complex, cj: Complex| {
+            (ni * ci - nj * cj) / (ni * ci + nj * cj)
+        };
+        let parallel = |ni: Complex, ci: Complex, nj: Complex, cj: Complex| {
+            (nj * ci - ni * cj) / (nj * ci + ni * cj)
+        };
+
+        // Phase difference of one round trip inside the film.
+        let scale = 4.0 * std::f64::consts::PI * self.thickness as f64 / RGB_WAVELENGTHS[channel];
+        let phase = (n1 * cos1 * Complex::new(scale, 0.0)).exp_i();
+
+        let airy =
+            |r01: Complex, r12: Complex| ((r01 + r12 * phase) / (one + r01 * r12 * phase)).norm();
+        let r_perpendicular = airy(
+            perpendicular(n0, cos0, n1, cos1),
+            perpendicular(n1, cos1, n2, cos2),
+        );
+        let r_parallel = airy(parallel(n0, cos0, n1, cos1), parallel(n1, cos1, n2, cos2));
+
+        ((r_perpendicular + r_parallel) * 0.5).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Rough metal with GGX distributed microfacets, tinted by the Fresnel term of its complex index
+/// of refraction, or of the thin `film` coating it. Smooth enough surfaces become a perfect
+/// mirror.
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    pub distribution: TrowbridgeReitz,
+    pub film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        match self.film {
+            Some(ref film) => film.conductor(cos_theta, &self.eta, &self.k),
+            None => fresnel_conductor(cos_theta, &self.eta, &self.k),
+        }
+    }
+}
+
+impl Bsdf for Conductor {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::black();
+        }
+
+        match reflection_half_vector(wo, wi) {
+            Some(wm) => {
+                let fresnel = self.fresnel(wo.dot(&wm).abs());
+                fresnel * self.distribution.reflection(wo, wi) as f32
+            }
+            None => Color::black(),
+        }
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let u = sampler.get_2d();
+        if self.distribution.is_smooth() {
+            let wi = make_reflection(-*wo, Vector3::up());
+            let fresnel = self.fresnel(wi.y.abs());
+            return Some(BsdfSample {
+                f: fresnel * (1.0 / wi.y.abs()) as f32,
+                wi,
+                pdf: 1.0,
+                specular: true,
+                pdf_is_proportional: false,
+            });
+        }
+
+        let wi = self.distribution.sample_reflection(wo, u)?;
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi,
+            specular: false,
+            pdf_is_proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+
+        self.distribution.reflection_pdf(wo, wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+/// Fraction of light reflected by a dielectric boundary with relative index of refraction `eta`,
+/// arriving at `cos_theta` from the normal. Negative cosines come from the inside.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_theta < 0.0 {
+        (-cos_theta.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta.min(1.0), eta)
+    };
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) * 0.5
+}
+
+// Refraction of `wi` through a boundary with normal `n` and relative index `eta`, together with
+// the index seen from the side of `wi`. `None` on total internal reflection.
+fn refract_about(wi: &Vector3, n: &Vector3, eta: f64) -> Option<(Vector3, f64)> {
+    let (n, eta, cos_i) = if wi.dot(n) < 0.0 {
+        (-*n, 1.0 / eta, -wi.dot(n))
+    } else {
+        (*n, eta, wi.dot(n))
+    };
+
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    Some((-*wi * (1.0 / eta) + n * (cos_i / eta - cos_t), eta))
+}
+
+/// Frosted glass: a dielectric boundary made of GGX distributed microfacets, each reflecting or
+/// refracting by its own Fresnel term, or by that of the thin `film` coating it.
+pub struct RoughDielectric {
+    pub index: f32,
+    pub distribution: TrowbridgeReitz,
+    pub film: Option<ThinFilm>,
+}
+
+impl RoughDielectric {
+    // Generalized half vector of a reflected or refracted pair of directions, facing up, with
+    // the relative index of refraction along the path (1 for reflection).
+    fn half_vector(&self, wo: &Vector3, wi: &Vector3) -> Option<(Vector3, f64)> {
+        if wo.y == 0.0 || wi.y == 0.0 {
+            return None;
+        }
+
+        let eta = if same_hemisphere(wo, wi) {
+            1.0
+        } else if wo.y > 0.0 {
+            self.index as f64
+        } else {
+            1.0 / self.index as f64
+        };
+
+        let wm = *wi * eta + *wo;
+        if wm.norm() == 0.0 {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.y < 0.0 { -wm } else { wm };
+
+        // Directions on the back of their microfacet can't be connected through it.
+        if wm.dot(wi) * wi.y < 0.0 || wm.dot(wo) * wo.y < 0.0 {
+            return None;
+        }
+
+        Some((wm, eta))
+    }
+
+    // Fraction of the light reflected by the microfacet `wm` per channel.
+    fn reflectance(&self, wo: &Vector3, wm: &Vector3) -> Color {
+        match self.film {
+            Some(ref film) => film.dielectric(wo.dot(wm), self.index as f64),
+            None => Color::white() * fresnel_dielectric(wo.dot(wm), self.index as f64) as f32,
+        }
+    }
+
+    // Average fractions of the light reflected and transmitted by the microfacet `wm`.
+    fn fresnel(&self, wo: &Vector3, wm: &Vector3) -> (f64, f64) {
+        let r = average(&self.reflectance(wo, wm));
+
+        (r, 1.0 - r)
+    }
+}
+
+impl Bsdf for RoughDielectric {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let (wm, eta) = match self.half_vector(wo, wi) {
+            Some(half_vector) => half_vector,
+            None => return Color::black(),
+        };
+
+        let r = self.reflectance(wo, &wm);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(wo, wi);
+        if same_hemisphere(wo, wi) {
+            r * (d * g / (4.0 * wo.y * wi.y).abs()) as f32
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2) * wi.y * wo.y;
+            (Color::white() - r) * (d * g * (wi.dot(&wm) * wo.dot(&wm) / denom).abs()) as f32
+        }
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        if wo.y == 0.0 {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(wo, u);
+        let (r, _) = self.fresnel(wo, &wm);
+        let wi = if uc < r {
+            let wi = make_reflection(-*wo, wm);
+            if !same_hemisphere(wo, &wi) {
+                return None;
+            }
+            wi
+        } else {
+            let (wi, _) = refract_about(wo, &wm, self.index as f64)?;
+            if same_hemisphere(wo, &wi) || wi.y == 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi,
+            specular: false,
+            pdf_is_proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        let (wm, eta) = match self.half_vector(wo, wi) {
+            Some(half_vector) => half_vector,
+            None => return 0.0,
+        };
+
+        let (r, t) = self.fresnel(wo, &wm);
+        let d_visible = self.distribution.d_visible(wo, &wm);
+        if same_hemisphere(wo, wi) {
+            d_visible / (4.0 * wo.dot(&wm).abs()) * r
+        } else {
+            // Change of variables from the half vector to the refracted direction.
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            d_visible * wi.dot(&wm).abs() / denom * t
+        }
+    }
+
+    fn eta(&self) -> f64 {
+        self.index as f64
+    }
+}
+
+/// Smooth boundary of a dielectric with the given index of refraction, reflecting or
+/// refracting as chosen by the Fresnel term.
+pub struct SpecularDielectric {
+    pub index: f32,
+    pub film: Option<ThinFilm>,
+}
+
+impl Bsdf for SpecularDielectric {
+    fn f(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::black()
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let incident = -*wo;
+        if let Some(ref film) = self.film {
+            // The film tints each lobe, which is picked by its average share of the energy.
+            let r = film.dielectric(wo.y, self.index as f64);
+            let kr = average(&r);
+            let reflection = make_reflection(incident, Vector3::up());
+            return match refract(incident, Vector3::up(), self.index) {
+                Some(wi) if sampler.get_1d() >= kr => {
+                    Some(tinted_specular_sample(wi, Color::white() - r, 1.0 - kr))
+                }
+                Some(_) => Some(tinted_specular_sample(reflection, r, kr)),
+                None => Some(tinted_specular_sample(reflection, r, 1.0)),
+            };
+        }
+
+        let kr = fresnel_dielectric(wo.y, self.index as f64);
+        match refract(incident, Vector3::up(), self.index) {
+            Some(wi) if sampler.get_1d() >= kr => Some(specular_sample(wi, 1.0 - kr)),
+            _ => Some(specular_sample(
+                make_reflection(incident, Vector3::up()),
+                kr,
+            )),
+        }
+    }
+
+    fn pdf(&self, _wo: &Vector3, _wi: &Vector3) -> f64 {
+        0.0
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn eta(&self) -> f64 {
+        self.index as f64
+    }
+}
+
+// Principled surfaces never become a perfect mirror, so all their lobes can be mixed.
+const PRINCIPLED_MIN_ROUGHNESS: f32 = 0.05;
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+/// Disney style principled surface, mixing a diffuse base with sheen and subsurface flattening,
+/// a GGX specular lobe going from dielectric to metal, a clear coat on top and rough
+/// transmission. Every parameter but `base_color` lies in `[0, 1]`.
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Dielectric reflectance at normal incidence, 0.5 being 4% like glass.
+    pub specular: f32,
+    pub sheen: f32,
+    pub clearcoat: f32,
+    pub transmission: f32,
+    pub subsurface: f32,
+}
+
+impl Principled {
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(self.roughness.max(PRINCIPLED_MIN_ROUGHNESS))
+    }
+
+    fn dielectric(&self) -> RoughDielectric {
+        // Index of refraction matching the specular reflectance.
+        let f0 = (0.08 * self.specular.clamp(0.0, 1.0)).sqrt();
+
+        RoughDielectric {
+            index: ((1.0 + f0) / (1.0 - f0)).max(1.01),
+            distribution: self.distribution(),
+            film: None,
+        }
+    }
+
+    // Weights of the diffuse, specular, clear coat and transmission lobes. Transmission only
+    // stands for the light refracted through, its reflection being the specular lobe.
+    fn weights(&self) -> [f32; 4] {
+        let dielectric = 1.0 - self.metallic;
+
+        [
+            dielectric * (1.0 - self.transmission),
+            1.0,
+            0.25 * self.clearcoat,
+            dielectric * self.transmission,
+        ]
+    }
+
+    // Probabilities of sampling each lobe, proportional to their weights.
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let weights = self.weights();
+        let total: f32 = weights.iter().sum();
+
+        let mut probabilities = [0.0; 4];
+        for (probability, weight) in probabilities.iter_mut().zip(weights.iter()) {
+            *probability = (weight / total) as f64;
+        }
+        probabilities
+    }
+
+    fn diffuse(&self, wo: &Vector3, wi: &Vector3, cos_d: f64) -> Color {
+        let (cos_o, cos_i) = (wo.y.abs(), wi.y.abs());
+        let (fo, fi) = (schlick_weight(cos_o), schlick_weight(cos_i));
+        let roughness = self.roughness as f64;
+
+        let lambert = (1.0 - 0.5 * fo) * (1.0 - 0.5 * fi);
+        let rr = 2.0 * roughness * cos_d * cos_d;
+        let retro_reflection = rr * (fo + fi + fo * fi * (rr - 1.0));
+
+        // Hanrahan-Krueger like flattening standing in for subsurface scattering.
+        let fss90 = roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fo) * (1.0 + (fss90 - 1.0) * fi);
+        let ss = 1.25 * (fss * (1.0 / (cos_o + cos_i) - 0.5) + 0.5);
+
+        let subsurface = self.subsurface as f64;
+        let diffuse = (lambert * (1.0 - subsurface) + ss * subsurface + retro_reflection)
+            / std::f64::consts::PI;
+        let sheen = self.sheen as f64 * schlick_weight(cos_d);
+
+        self.base_color * diffuse as f32 + Color::white() * sheen as f32
+    }
+}
+
+// Schlick's approximation of the Fresnel falloff, `(1 - cos)^5`.
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn schlick(f0: Color, cos_theta: f64) -> Color {
+    let weight = schlick_weight(cos_theta) as f32;
+
+    f0 * (1.0 - weight) + Color::white() * weight
+}
+
+impl Bsdf for Principled {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let [diffuse, specular, clearcoat, transmission] = self.weights();
+        let mut f = Color::black();
+
+        if let Some(wm) = reflection_half_vector(wo, wi) {
+            let cos_d = upper(wi).dot(&wm);
+
+            let f0 = Color::white() * (0.08 * self.specular) * (1.0 - self.metallic)
+                + self.base_color * self.metallic;
+            let clearcoat_distribution = TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS);
+
+            f = self.diffuse(wo, wi, cos_d) * diffuse
+                + schlick(f0, cos_d) * (self.distribution().reflection(wo, wi) as f32 * specular)
+                + schlick(Color::white() * 0.04, cos_d)
+                    * (clearcoat_distribution.reflection(wo, wi) as f32 * clearcoat);
+        }
+
+        if transmission > 0.0 && !same_hemisphere(wo, wi) {
+            f = f + self.base_color * self.dielectric().f(wo, wi) * transmission;
+        }
+        f
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let [diffuse, specular, clearcoat, _] = self.lobe_probabilities();
+        let uc = sampler.get_1d();
+
+        let wi = if uc < diffuse {
+            sample_cosine_lobe(wo, sampler.get_2d())
+        } else if uc < diffuse + specular {
+            self.distribution()
+                .sample_reflection(wo, sampler.get_2d())?
+        } else if uc < diffuse + specular + clearcoat {
+            TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS).sample_reflection(wo, sampler.get_2d())?
+        } else {
+            // Reflections off the dielectric belong to the specular lobe.
+            let wi = self.dielectric().sample_f(wo, sampler)?.wi;
+            if same_hemisphere(wo, &wi) {
+                return None;
+            }
+            wi
+        };
+
+        Some(BsdfSample {
+            f: self.f(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            wi,
+            specular: false,
+            pdf_is_proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_probabilities();
+        let mut pdf = 0.0;
+
+        if same_hemisphere(wo, wi) {
+            pdf += diffuse * cosine_hemisphere_pdf(wi.y.abs())
+                + specular * self.distribution().reflection_pdf(wo, wi)
+                + clearcoat * TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS).reflection_pdf(wo, wi);
+        }
+        if transmission > 0.0 && !same_hemisphere(wo, wi) {
+            pdf += transmission * self.dielectric().pdf(wo, wi);
+        }
+        pdf
+    }
+
+    fn eta(&self) -> f64 {
+        self.dielectric().index as f64
+    }
+}
+
+// Bounces followed between the layers of a `Layered` BSDF before the light is given up on.
+const LAYERED_MAX_DEPTH: usize = 10;
+
+/// `coat`, usually a dielectric boundary, layered over an opaque `base`, with a slab of
+/// `thickness` in between absorbing `absorption` per unit distance, like varnish or car paint.
+/// Light bouncing between both layers is followed by a position-free random walk as in
+/// pbrt-v4, which makes `f` and `pdf` stochastic estimates.
+pub struct Layered {
+    pub coat: Box<dyn Bsdf>,
+    pub base: Box<dyn Bsdf>,
+    pub absorption: Color,
+    pub thickness: f32,
+}
+
+impl Layered {
+    // Fraction of the light crossing the slab along `w`.
+    fn transmittance(&self, w: &Vector3) -> Color {
+        let distance = self.thickness / w.y.abs() as f32;
+        let channel = |absorption: f32| (-absorption * distance).exp();
+
+        Color {
+            r: channel(self.absorption.r),
+            g: channel(self.absorption.g),
+            b: channel(self.absorption.b),
+        }
+    }
+
+    // Scattering by `layer` of light travelling along `w` inside the slab, kept only when it
+    // goes back into the slab.
+    fn bounce(
+        &self,
+        layer: &dyn Bsdf,
+        w: &Vector3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let sample = valid(layer.sample_f(&-*w, sampler)?)?;
+        if same_hemisphere(&-*w, &sample.wi) {
+            Some(sample)
+        } else {
+            None
+        }
+    }
+
+    // Light from `w` refracted by the coat into the slab, `None` when reflected instead.
+    fn enter(&self, w: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let sample = valid(self.coat.sample_f(w, sampler)?)?;
+        if same_hemisphere(w, &sample.wi) {
+            None
+        } else {
+            Some(sample)
+        }
+    }
+}
+
+fn valid(sample: BsdfSample) -> Option<BsdfSample> {
+    if sample.pdf == 0.0 || sample.f.is_black() || sample.wi.y == 0.0 {
+        None
+    } else {
+        Some(sample)
+    }
+}
+
+fn max_component(color: &Color) -> f32 {
+    color.r.max(color.g).max(color.b)
+}
+
+// Both sides of a layered surface are coated, directions from below are mirrored above.
+fn coated_side(wo: &Vector3, wi: &Vector3) -> (Vector3, Vector3) {
+    if wo.y < 0.0 {
+        (-*wo, -*wi)
+    } else {
+        (*wo, *wi)
+    }
+}
+
+// Samples for the estimates of `f` and `pdf`, the same for the same directions so that they
+// agree with each other and between calls.
+fn stochastic_sampler(wo: &Vector3, wi: &Vector3) -> HashSampler {
+    HashSampler::new(&[wo.x, wo.y, wo.z, wi.x, wi.y, wi.z])
+}
+
+impl Bsdf for Layered {
+    fn f(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let (wo, wi) = coated_side(wo, wi);
+        if !same_hemisphere(&wo, &wi) {
+            return Color::black();
+        }
+
+        let mut sampler = stochastic_sampler(&wo, &wi);
+        let mut f = self.coat.f(&wo, &wi);
+
+        // Paths entering the slab towards `wo` and `wi`, the latter connected to at the base.
+        let (wos, wis) = match (self.enter(&wo, &mut sampler), self.enter(&wi, &mut sampler)) {
+            (Some(wos), Some(wis)) => (wos, wis),
+            _ => return f,
+        };
+        let exit = wis.f * (wis.wi.y.abs() / wis.pdf) as f32 * self.transmittance(&wis.wi);
+
+        let mut beta = wos.f * (wos.wi.y.abs() / wos.pdf) as f32;
+        let mut w = wos.wi;
+        let mut at_base = false;
+        for depth in 0..LAYERED_MAX_DEPTH {
+            if depth > 3 && max_component(&beta) < 0.25 {
+                let q = (1.0 - max_component(&beta)).max(0.0);
+                if sampler.get_1d() < q as f64 {
+                    break;
+                }
+                beta = beta * (1.0 / (1.0 - q));
+            }
+
+            beta = beta * self.transmittance(&w);
+            at_base = !at_base;
+
+            if !at_base {
+                let sample = match self.bounce(self.coat.as_ref(), &w, &mut sampler) {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                beta = beta * sample.f * (sample.wi.y.abs() / sample.pdf) as f32;
+                w = sample.wi;
+                continue;
+            }
+
+            // Rough bases connect to the path towards `wi`, smooth ones leave through a rough
+            // coat by evaluating it.
+            if !self.base.is_specular() {
+                f = f + beta * self.base.f(&-w, &-wis.wi) * exit;
+            }
+
+            let sample = match self.bounce(self.base.as_ref(), &w, &mut sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            beta = beta * sample.f * (sample.wi.y.abs() / sample.pdf) as f32;
+            w = sample.wi;
+
+            if self.base.is_specular() && !self.coat.is_specular() {
+                f = f + beta * self.transmittance(&w) * self.coat.f(&-w, &wi);
+            }
+        }
+
+        f
+    }
+
+    fn sample_f(&self, wo: &Vector3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let flip = wo.y < 0.0;
+        let wo = if flip { -*wo } else { *wo };
+        let oriented = |w: Vector3| if flip { -w } else { w };
+
+        let sample = valid(self.coat.sample_f(&wo, sampler)?)?;
+        if same_hemisphere(&wo, &sample.wi) {
+            return Some(BsdfSample {
+                wi: oriented(sample.wi),
+                pdf_is_proportional: true,
+                ..sample
+            });
+        }
+
+        // Random walk through the slab until the light refracts out of the coat.
+        let mut f = sample.f * sample.wi.y.abs() as f32;
+        let mut pdf = sample.pdf;
+        let mut specular = sample.specular;
+        let mut w = sample.wi;
+        let mut at_base = false;
+        for depth in 0..LAYERED_MAX_DEPTH {
+            let rr_beta = max_component(&f) as f64 / pdf;
+            if depth > 3 && rr_beta < 0.25 {
+                let q = (1.0 - rr_beta).max(0.0);
+                if sampler.get_1d() < q {
+                    return None;
+                }
+                pdf *= 1.0 - q;
+            }
+
+            f = f * self.transmittance(&w);
+            at_base = !at_base;
+
+            let layer = if at_base { &self.base } else { &self.coat };
+            let sample = valid(layer.sample_f(&-w, sampler)?)?;
+            f = f * sample.f;
+            pdf *= sample.pdf;
+            specular &= sample.specular;
+
+            if !same_hemisphere(&-w, &sample.wi) {
+                // Light going through the base is lost, it's considered opaque.
+                if at_base {
+                    return None;
+                }
+
+                return Some(BsdfSample {
+                    wi: oriented(sample.wi),
+                    f,
+                    pdf,
+                    specular,
+                    pdf_is_proportional: true,
+                });
+            }
+
+            f = f * sample.wi.y.abs() as f32;
+            w = sample.wi;
+        }
+
+        None
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        let (wo, wi) = coated_side(wo, wi);
+        if self.is_specular() || !same_hemisphere(&wo, &wi) {
+            return 0.0;
+        }
+
+        let mut sampler = stochastic_sampler(&wo, &wi);
+        let mut pdf = self.coat.pdf(&wo, &wi);
+
+        // Density of the paths refracting in, bouncing once off the base and refracting out,
+        // following the same strategies as `f`.
+        if let (Some(wos), Some(wis)) =
+            (self.enter(&wo, &mut sampler), self.enter(&wi, &mut sampler))
+        {
+            if !self.base.is_specular() {
+                pdf += self.base.pdf(&-wos.wi, &-wis.wi);
+            } else if !self.coat.is_specular() {
+                if let Some(sample) = self.bounce(self.base.as_ref(), &wos.wi, &mut sampler) {
+                    pdf += self.coat.pdf(&-sample.wi, &wi);
+                }
+            }
+        }
+
+        // Mixed with a uniform density, the estimate alone may miss some directions.
+        0.1 / (4.0 * std::f64::consts::PI) + 0.9 * pdf
+    }
+
+    fn is_specular(&self) -> bool {
+        self.coat.is_specular() && self.base.is_specular()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COSINES: [f64; 5] = [1.0, 0.8, 0.5, 0.2, 0.05];
+
+    fn assert_gray(color: Color, expected: f64) {
+        for &value in &[color.r, color.g, color.b] {
+            assert!(
+                (value as f64 - expected).abs() < 1e-5,
+                "{} != {}",
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn vanishing_film_on_a_dielectric_is_fresnel() {
+        // Without thickness, or with the index of the medium above, the film isn't there.
+        let films = [
+            ThinFilm {
+                thickness: 0.0,
+                index: 1.33,
+            },
+            ThinFilm {
+                thickness: 250.0,
+                index: 1.0,
+            },
+        ];
+
+        for film in &films {
+            for &cos_theta in &COSINES {
+                assert_gray(
+                    film.dielectric(cos_theta, 1.5),
+                    fresnel_dielectric(cos_theta, 1.5),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_dielectric_picks_lobes_by_fresnel() {
+        let dielectric = SpecularDielectric {
+            index: 1.5,
+            film: None,
+        };
+
+        // Grazing light, from outside and from inside past the critical angle.
+        for &cos_theta in &[0.2f64, -0.2] {
+            let wo = Vector3 {
+                x: (1.0 - cos_theta * cos_theta).sqrt(),
+                y: cos_theta,
+                z: 0.0,
+            };
+            let kr = fresnel_dielectric(cos_theta, 1.5);
+            let mut sampler = HashSampler::new(&[cos_theta]);
+
+            for _ in 0..16 {
+                let sample = dielectric.sample_f(&wo, &mut sampler).unwrap();
+                let expected = if same_hemisphere(&wo, &sample.wi) {
+                    kr
+                } else {
+                    1.0 - kr
+                };
+                assert!((sample.pdf - expected).abs() < 1e-9, "{}", sample.pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_thickness_film_from_inside_is_fresnel() {
+        let film = ThinFilm {
+            thickness: 0.0,
+            index: 1.33,
+        };
+
+        // Includes total internal reflection past the critical angle.
+        for &cos_theta in &COSINES {
+            assert_gray(
+                film.dielectric(-cos_theta, 1.5),
+                fresnel_dielectric(-cos_theta, 1.5),
+            );
+        }
+    }
+
+    #[test]
+    fn zero_thickness_film_on_a_conductor_is_fresnel() {
+        let film = ThinFilm {
+            thickness: 0.0,
+            index: 1.33,
+        };
+        // Gold.
+        let eta = Color {
+            r: 0.143,
+            g: 0.374,
+            b: 1.442,
+        };
+        let k = Color {
+            r: 3.983,
+            g: 2.385,
+            b: 1.603,
+        };
+
+        for &cos_theta in &COSINES {
+            let filmed = film.conductor(cos_theta, &eta, &k);
+            let bare = fresnel_conductor(cos_theta, &eta, &k);
+            for &(a, b) in &[(filmed.r, bare.r), (filmed.g, bare.g), (filmed.b, bare.b)] {
+                assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn layered_estimates_are_deterministic() {
+        let layered = Layered {
+            coat: Box::new(SpecularDielectric {
+                index: 1.5,
+                film: None,
+            }),
+            base: Box::new(Lambertian {
+                color: Color::white(),
+            }),
+            absorption: Color::black(),
+            thickness: 0.01,
+        };
+        let wo = Vector3 {
+            x: 0.3,
+            y: 0.8,
+            z: 0.1,
+        }
+        .normalize();
+        let wi = Vector3 {
+            x: -0.5,
+            y: 0.6,
+            z: 0.2,
+        }
+        .normalize();
+
+        let f = layered.f(&wo, &wi);
+        assert!(!f.is_black());
+        let again = layered.f(&wo, &wi);
+        assert_eq!((f.r, f.g, f.b), (again.r, again.g, again.b));
+        assert_eq!(layered.pdf(&wo, &wi), layered.pdf(&wo, &wi));
+    }
+
+    // Fraction of the light arriving along `wo` that is scattered, estimated by sampling.
+    fn albedo(bsdf: &dyn Bsdf, wo: &Vector3) -> f64 {
+        let count = 100_000;
+        let mut sampler = HashSampler::new(&[wo.x, wo.y, wo.z]);
+        let total: f64 = (0..count)
+            .filter_map(|_| bsdf.sample_f(wo, &mut sampler))
+            .filter(|sample| sample.pdf > 0.0)
+            .map(|sample| average(&sample.f) * sample.wi.y.abs() / sample.pdf)
+            .sum();
+
+        total / count as f64
+    }
+
+    #[test]
+    fn principled_transmission_conserves_energy() {
+        for &roughness in &[0.1, 0.5, 1.0] {
+            let principled = Principled {
+                base_color: Color::white(),
+                metallic: 0.0,
+                roughness,
+                specular: 0.5,
+                sheen: 0.0,
+                clearcoat: 0.0,
+                transmission: 1.0,
+                subsurface: 0.0,
+            };
+
+            for &cos_theta in &[1.0f64, 0.7, 0.3] {
+                let wo = Vector3 {
+                    x: (1.0 - cos_theta * cos_theta).sqrt(),
+                    y: cos_theta,
+                    z: 0.0,
+                };
+                let albedo = albedo(&principled, &wo);
+                // Up to the noise of the estimate.
+                assert!(albedo < 1.01 && albedo > 0.5, "{}: {}", roughness, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn principled_transmission_reflects_untinted() {
+        // Black glass still reflects like glass, about 4% at normal incidence.
+        let principled = Principled {
+            base_color: Color::black(),
+            metallic: 0.0,
+            roughness: 0.1,
+            specular: 0.5,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            transmission: 1.0,
+            subsurface: 0.0,
+        };
+
+        let albedo = albedo(&principled, &Vector3::up());
+        assert!((albedo - 0.04).abs() < 0.005, "{}", albedo);
+    }
+
+    #[test]
+    fn refracted_radiance_is_scaled_by_the_relative_index() {
+        let rough = || RoughDielectric {
+            index: 1.5,
+            distribution: TrowbridgeReitz::new(0.3),
+            film: None,
+        };
+        let radiance = SurfaceBsdf::new(Vector3::up(), Box::new(rough()));
+        let mut importance = SurfaceBsdf::new(Vector3::up(), Box::new(rough()));
+        importance.mode = TransportMode::Importance;
+
+        let wo = Vector3::up();
+        let wi = Vector3 {
+            x: 0.3,
+            y: -1.0,
+            z: 0.0,
+        }
+        .normalize();
+        let (r, i) = (radiance.f(&wo, &wi), importance.f(&wo, &wi));
+        assert!(i.g > 0.0);
+        assert!((r.g / i.g - 1.0 / (1.5 * 1.5)).abs() < 1e-5);
+
+        // Leaving the denser side the radiance grows back.
+        let (r, i) = (radiance.f(&wi, &wo), importance.f(&wi, &wo));
+        assert!((r.g / i.g - 1.5 * 1.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,148 @@
+use super::point::Point;
+use super::rendering::Ray;
+use super::scene::Scene;
+use super::vector3::Vector3;
+
+/// Pinhole camera sitting at the origin and looking down `-z`, matching `Ray::create_prime`.
+pub struct Camera {
+    pub width: u32,
+    pub height: u32,
+    fov_adjustment: f64,
+    aspect_ratio: f64,
+    // Area of the image plane at distance 1 from the pinhole.
+    area: f64,
+}
+
+impl Camera {
+    pub fn new(scene: &Scene) -> Camera {
+        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
+        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
+
+        Camera {
+            width: scene.width,
+            height: scene.height,
+            fov_adjustment,
+            aspect_ratio,
+            area: (2.0 * fov_adjustment * aspect_ratio) * (2.0 * fov_adjustment),
+        }
+    }
+
+    pub fn origin(&self) -> Point {
+        Point::zero()
+    }
+
+    pub fn forward(&self) -> Vector3 {
+        Vector3::backward()
+    }
+
+    /// Ray through pixel `(x, y)`, jittered inside the pixel by `u`.
+    pub fn generate_ray(&self, x: u32, y: u32, u: (f64, f64)) -> Ray {
+        let sensor_x = ((((x as f64 + u.0) / self.width as f64) * 2.0 - 1.0) * self.aspect_ratio)
+            * self.fov_adjustment;
+        let sensor_y = (1.0 - ((y as f64 + u.1) / self.height as f64) * 2.0) * self.fov_adjustment;
+
+        Ray {
+            origin: self.origin(),
+            direction: Vector3 {
+                x: sensor_x,
+                y: sensor_y,
+                z: -1.0,
+            }
+            .normalize(),
+        }
+    }
+
+    /// Pixel seeing `point`, if it is inside the field of view.
+    pub fn raster_position(&self, point: &Point) -> Option<(u32, u32)> {
+        let direction = *point - self.origin();
+        if direction.z >= 0.0 {
+            return None;
+        }
+
+        let sensor_x = direction.x / -direction.z;
+        let sensor_y = direction.y / -direction.z;
+        let raster_x = (sensor_x / (self.aspect_ratio * self.fov_adjustment) + 1.0) * 0.5;
+        let raster_y = (1.0 - sensor_y / self.fov_adjustment) * 0.5;
+
+        if !(0.0..1.0).contains(&raster_x) || !(0.0..1.0).contains(&raster_y) {
+            return None;
+        }
+
+        Some((
+            (raster_x * self.width as f64) as u32,
+            (raster_y * self.height as f64) as u32,
+        ))
+    }
+
+    /// Importance emitted along `direction` (pointing away from the camera).
+    pub fn importance(&self, direction: &Vector3) -> f64 {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let cos2_theta = cos_theta * cos_theta;
+
+        1.0 / (self.area * cos2_theta * cos2_theta)
+    }
+
+    /// Solid angle density of generating a ray along `direction`.
+    pub fn pdf_direction(&self, direction: &Vector3) -> f64 {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+
+        1.0 / (self.area * cos_theta * cos_theta * cos_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::new(&Scene {
+            width: 80,
+            height: 40,
+            fov: 90.0,
+            entities: vec![],
+            lights: vec![],
+        })
+    }
+
+    #[test]
+    fn raster_position_inverts_generate_ray() {
+        let camera = camera();
+
+        for &(x, y) in &[(0, 0), (79, 39), (40, 20), (13, 31)] {
+            let ray = camera.generate_ray(x, y, (0.5, 0.5));
+            let point = ray.origin + ray.direction * 3.0;
+            assert_eq!(camera.raster_position(&point), Some((x, y)));
+        }
+    }
+
+    #[test]
+    fn raster_position_rejects_points_out_of_view() {
+        let camera = camera();
+
+        assert_eq!(camera.raster_position(&Point::new(0.0, 0.0, 1.0)), None);
+        assert_eq!(camera.raster_position(&Point::new(10.0, 0.0, -1.0)), None);
+        assert_eq!(camera.raster_position(&Point::new(0.0, -10.0, -1.0)), None);
+    }
+
+    #[test]
+    fn importance_over_pdf_cancels_the_cosine() {
+        let camera = camera();
+
+        for &(x, y) in &[(0, 0), (40, 20), (70, 5)] {
+            let direction = camera.generate_ray(x, y, (0.5, 0.5)).direction;
+            let cos_theta = direction.dot(&camera.forward());
+            let ratio =
+                camera.importance(&direction) * cos_theta / camera.pdf_direction(&direction);
+            assert!((ratio - 1.0).abs() < 1e-9, "{}", ratio);
+        }
+
+        assert_eq!(camera.importance(&Vector3::forward()), 0.0);
+        assert_eq!(camera.pdf_direction(&Vector3::forward()), 0.0);
+    }
+}
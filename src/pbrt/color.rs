@@ -1,5 +1,5 @@
 use image::{Pixel, Rgba};
-use std::ops::{Add, Mul};
+use std::ops::{Add, Mul, Sub};
 
 const GAMMA: f32 = 2.2;
 
@@ -35,15 +35,23 @@ impl Color {
         }
     }
 
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+
     pub fn clamp(&self) -> Color {
         Color {
-            r: self.r.min(1.0).max(0.0),
-            g: self.g.min(1.0).max(0.0),
-            b: self.b.min(1.0).max(0.0),
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
         }
     }
 
-    pub fn to_rgba(&self) -> Rgba<u8> {
+    pub fn to_rgba(self) -> Rgba<u8> {
         Rgba::from_channels(
             (gamma_encode(self.r) * 255.0) as u8,
             (gamma_encode(self.g) * 255.0) as u8,
@@ -59,6 +67,15 @@ impl Color {
             b: gamma_decode((rgba.data[2] as f32) / 255.0),
         }
     }
+
+    /// Linear sRGB of a CIE XYZ tristimulus value, negative channels clipped.
+    pub fn from_xyz(x: f64, y: f64, z: f64) -> Color {
+        Color {
+            r: (3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z).max(0.0) as f32,
+            g: (-0.969_266_0 * x + 1.876_010_8 * y + 0.041_556_0 * z).max(0.0) as f32,
+            b: (0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z).max(0.0) as f32,
+        }
+    }
 }
 
 impl Mul for Color {
@@ -104,3 +121,15 @@ impl Add for Color {
         }
     }
 }
+
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Color) -> Color {
+        Color {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+        }
+    }
+}
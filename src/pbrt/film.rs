@@ -0,0 +1,84 @@
+use image::{ImageBuffer, Rgba};
+
+use super::color::Color;
+
+/// Accumulates per-pixel samples and splats contributed at arbitrary pixels.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    exposure: f32,
+    pixels: Vec<Color>,
+    sample_counts: Vec<u32>,
+    splats: Vec<Color>,
+    splat_scale: f32,
+    // Whether samples are CIE XYZ tristimulus values rather than linear RGB.
+    xyz: bool,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, exposure: f32) -> Film {
+        let size = (width * height) as usize;
+
+        Film {
+            width,
+            height,
+            exposure,
+            pixels: vec![Color::black(); size],
+            sample_counts: vec![0; size],
+            splats: vec![Color::black(); size],
+            splat_scale: 1.0,
+            xyz: false,
+        }
+    }
+
+    /// Film taking CIE XYZ samples, in the channels of a `Color`, developed to RGB.
+    pub fn new_xyz(width: u32, height: u32, exposure: f32) -> Film {
+        Film {
+            xyz: true,
+            ..Film::new(width, height, exposure)
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        let index = self.index(x, y);
+        self.pixels[index] = self.pixels[index] + color;
+        self.sample_counts[index] += 1;
+    }
+
+    pub fn add_splat(&mut self, x: u32, y: u32, color: Color) {
+        let index = self.index(x, y);
+        self.splats[index] = self.splats[index] + color;
+    }
+
+    /// Splats are divided by this value when the image is developed, usually the sample count.
+    pub fn set_splat_scale(&mut self, scale: f32) {
+        self.splat_scale = scale;
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let index = self.index(x, y);
+        let count = self.sample_counts[index];
+        let sampled = if count > 0 {
+            self.pixels[index] * (1.0 / count as f32)
+        } else {
+            Color::black()
+        };
+
+        let color = (sampled + self.splats[index] * (1.0 / self.splat_scale)) * self.exposure;
+        if self.xyz {
+            Color::from_xyz(color.r as f64, color.g as f64, color.b as f64)
+        } else {
+            color
+        }
+    }
+
+    pub fn to_image(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            self.pixel(x, y).clamp().to_rgba()
+        })
+    }
+}
@@ -0,0 +1,351 @@
+use std::fs;
+use std::path::PathBuf;
+
+use super::sampling::create_coordinate_system;
+use super::scene::Power;
+use super::vector3::Vector3;
+
+// Photometric type of the luminaires this reads, with a vertical polar axis.
+const TYPE_C: f64 = 1.0;
+
+/// Candela distribution of a type C luminaire read from an IESNA LM-63 photometric file.
+/// Vertical angles are measured from the nadir, horizontal angles around it, both in degrees.
+pub struct IesProfile {
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    // One row of vertical samples per horizontal angle, in candela.
+    candela: Vec<Vec<f64>>,
+    /// Rated lumens of the lamps the candela values are given for, or the flux of the
+    /// distribution itself for absolute photometry.
+    pub lumens: f64,
+}
+
+impl IesProfile {
+    pub fn load(path: PathBuf) -> Result<IesProfile, String> {
+        let contents = fs::read_to_string(&path)
+            .map_err(|_| format!("Unable to open IES file: {:?}", &path))?;
+
+        IesProfile::parse(&contents).map_err(|error| format!("{}: {:?}", error, &path))
+    }
+
+    pub fn parse(contents: &str) -> Result<IesProfile, String> {
+        let mut lines = contents.lines();
+        let tilt = lines
+            .by_ref()
+            .map(|line| line.trim())
+            .find(|line| line.starts_with("TILT="))
+            .ok_or_else(|| String::from("Missing TILT line in IES file"))?;
+
+        let mut values = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number in IES file: {}", token))
+            });
+        let mut next = || {
+            values
+                .next()
+                .unwrap_or_else(|| Err(String::from("Unexpected end of IES file")))
+        };
+
+        // Lamp tilt multipliers don't change the shape of the distribution, skip them.
+        if tilt == "TILT=INCLUDE" {
+            next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        }
+
+        let lamps = next()?;
+        let lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        for _ in 0..4 {
+            // Units and luminous dimensions.
+            next()?;
+        }
+        let ballast_factor = next()?;
+        for _ in 0..2 {
+            // Ballast lamp photometric factor, unused since LM-63-2002, and input watts.
+            next()?;
+        }
+
+        if photometric_type != TYPE_C {
+            return Err(format!(
+                "Unsupported photometric type {} in IES file, only type C is supported",
+                photometric_type
+            ));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(String::from("Empty candela table in IES file"));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, String>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, String>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| next().map(|value| value * multiplier * ballast_factor))
+                    .collect::<Result<Vec<f64>, String>>()
+            })
+            .collect::<Result<Vec<Vec<f64>>, String>>()?;
+
+        let mut profile = IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            lumens: lamps * lumens_per_lamp,
+        };
+        // Absolute photometry gives -1 lumens per lamp.
+        if lumens_per_lamp <= 0.0 {
+            profile.lumens = profile.flux();
+        }
+
+        Ok(profile)
+    }
+
+    /// Scales the candela values for lamps emitting `power` instead of the rated lumens.
+    pub fn set_power(&mut self, power: Power) -> Result<(), String> {
+        if self.lumens <= 0.0 {
+            return Err(String::from("Dark photometric profiles can't have a power"));
+        }
+
+        let scale = power.lumens() as f64 / self.lumens;
+        for value in self.candela.iter_mut().flatten() {
+            *value *= scale;
+        }
+        self.lumens = power.lumens() as f64;
+        Ok(())
+    }
+
+    /// Candela towards `direction` for a luminaire whose nadir points along `axis`.
+    pub fn intensity(&self, axis: &Vector3, direction: &Vector3) -> f64 {
+        let axis = axis.normalize();
+        let (tangent, bitangent) = create_coordinate_system(&axis);
+        let vertical = direction.dot(&axis).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = direction
+            .dot(&bitangent)
+            .atan2(direction.dot(&tangent))
+            .to_degrees();
+
+        self.candela(vertical, horizontal)
+    }
+
+    // Luminous flux of the distribution, integrating the candela over the sphere.
+    fn flux(&self) -> f64 {
+        let (rows, columns) = (180, 360);
+        let (d_theta, d_phi) = (
+            std::f64::consts::PI / rows as f64,
+            2.0 * std::f64::consts::PI / columns as f64,
+        );
+
+        (0..rows)
+            .map(|i| {
+                let theta = (i as f64 + 0.5) * d_theta;
+                let ring: f64 = (0..columns)
+                    .map(|j| {
+                        let phi = (j as f64 + 0.5) * d_phi;
+                        self.candela(theta.to_degrees(), phi.to_degrees())
+                    })
+                    .sum();
+                ring * theta.sin() * d_theta * d_phi
+            })
+            .sum()
+    }
+
+    fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let first = self.vertical_angles[0];
+        let last = self.vertical_angles[self.vertical_angles.len() - 1];
+        if vertical < first || vertical > last {
+            return 0.0;
+        }
+
+        // Unfold the horizontal angle into the range covered by the symmetry of the file.
+        let mut horizontal = horizontal.rem_euclid(360.0);
+        let horizontal_first = self.horizontal_angles[0];
+        let horizontal_last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if horizontal_first >= 90.0 && horizontal_last <= 270.0 {
+            // Symmetric about the 90-270 degree plane.
+            if !(90.0..=270.0).contains(&horizontal) {
+                horizontal = (180.0 - horizontal).rem_euclid(360.0);
+            }
+        } else if horizontal_last <= 180.0 && horizontal > 180.0 {
+            horizontal = 360.0 - horizontal;
+        }
+        if horizontal_last <= 90.0 && horizontal > 90.0 {
+            horizontal = 180.0 - horizontal;
+        }
+
+        let (h, th) = interval(&self.horizontal_angles, horizontal);
+        let (v, tv) = interval(&self.vertical_angles, vertical);
+        let row = |i: usize| {
+            let values = &self.candela[i];
+            let next = (v + 1).min(values.len() - 1);
+            values[v] * (1.0 - tv) + values[next] * tv
+        };
+
+        let next = (h + 1).min(self.candela.len() - 1);
+        row(h) * (1.0 - th) + row(next) * th
+    }
+}
+
+// Segment of the sorted `angles` containing `value` and the position inside it.
+fn interval(angles: &[f64], value: f64) -> (usize, f64) {
+    if angles.len() == 1 {
+        return (0, 0.0);
+    }
+
+    let index = angles
+        .partition_point(|&angle| angle <= value)
+        .saturating_sub(1)
+        .min(angles.len() - 2);
+    let width = angles[index + 1] - angles[index];
+    let t = if width > 0.0 {
+        ((value - angles[index]) / width).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (index, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quadrant symmetric luminaire: 100 cd straight down along 0 degrees, 50 cd along 90.
+    const QUADRANT: &str = "IESNA:LM-63-2002
+[TEST] quadrant
+TILT=NONE
+1 1000 2 3 2 1 1 0 0 0
+1 1 100
+0 45 90
+0 90
+50 25 0
+25 10 0
+";
+
+    #[test]
+    fn parse_reads_the_candela_table() {
+        let profile = IesProfile::parse(QUADRANT).unwrap();
+
+        assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.horizontal_angles, vec![0.0, 90.0]);
+        // Scaled by the candela multiplier.
+        assert_eq!(
+            profile.candela,
+            vec![vec![100.0, 50.0, 0.0], vec![50.0, 20.0, 0.0]]
+        );
+        // One lamp of 1000 lumens.
+        assert_eq!(profile.lumens, 1000.0);
+    }
+
+    #[test]
+    fn parse_rejects_other_photometric_types() {
+        for photometric_type in ["2", "3"] {
+            let contents = QUADRANT.replace("3 2 1 1", &format!("3 2 {} 1", photometric_type));
+            assert!(IesProfile::parse(&contents).is_err());
+        }
+    }
+
+    #[test]
+    fn absolute_photometry_integrates_the_flux() {
+        // An isotropic 100 cd source, with -1 lumens per lamp.
+        let contents = "TILT=NONE
+1 -1 1 2 1 1 1 0 0 0
+1 1 100
+0 180
+0
+100 100
+";
+        let profile = IesProfile::parse(contents).unwrap();
+
+        let flux = 400.0 * std::f64::consts::PI;
+        assert!((profile.lumens - flux).abs() < 1e-4 * flux);
+    }
+
+    #[test]
+    fn parse_skips_tilt_multipliers() {
+        let contents = "TILT=INCLUDE
+1
+3 0 45 90 1 1 1
+1 1000 1 2 1 1 1 0 0 0
+1 1 100
+0 90
+0
+40 20
+";
+        let profile = IesProfile::parse(contents).unwrap();
+
+        assert_eq!(profile.candela, vec![vec![40.0, 20.0]]);
+
+        // Scaled by the ballast factor.
+        let dimmed = contents.replace("1 1 100", "0.5 1 100");
+        let profile = IesProfile::parse(&dimmed).unwrap();
+        assert_eq!(profile.candela, vec![vec![20.0, 10.0]]);
+    }
+
+    #[test]
+    fn parse_rejects_truncated_files() {
+        assert!(IesProfile::parse("1 1000 1").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 2 1").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 2 1 1 1 0 0 0 1 1 x").is_err());
+    }
+
+    #[test]
+    fn horizontal_angles_unfold_by_symmetry() {
+        let profile = IesProfile::parse(QUADRANT).unwrap();
+
+        assert_eq!(profile.candela(45.0, 0.0), 50.0);
+        assert_eq!(profile.candela(45.0, 90.0), 20.0);
+        assert_eq!(profile.candela(45.0, 45.0), 35.0);
+        // Mirrored around 90 and then 180 degrees.
+        assert_eq!(profile.candela(45.0, 180.0), 50.0);
+        assert_eq!(profile.candela(45.0, 270.0), 20.0);
+        assert_eq!(profile.candela(45.0, -45.0), 35.0);
+        assert_eq!(profile.candela(45.0, 135.0), 35.0);
+        // Outside of the vertical range.
+        assert_eq!(profile.candela(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn horizontal_angles_unfold_around_the_90_270_plane() {
+        let contents = "TILT=NONE
+1 1000 1 1 3 1 1 0 0 0
+1 1 100
+0
+90 180 270
+10 20 30
+";
+        let profile = IesProfile::parse(contents).unwrap();
+
+        assert_eq!(profile.candela(0.0, 180.0), 20.0);
+        assert_eq!(profile.candela(0.0, 0.0), 20.0);
+        // Mirrored onto 135 and 225 degrees.
+        assert_eq!(profile.candela(0.0, 45.0), 15.0);
+        assert_eq!(profile.candela(0.0, 315.0), 25.0);
+        assert_eq!(profile.candela(0.0, -45.0), 25.0);
+    }
+
+    #[test]
+    fn intensity_keeps_the_photometric_scale() {
+        let mut profile = IesProfile::parse(QUADRANT).unwrap();
+
+        assert_eq!(profile.intensity(&Vector3::down(), &Vector3::down()), 100.0);
+        assert_eq!(profile.intensity(&Vector3::down(), &Vector3::up()), 0.0);
+
+        // Lamps twice as bright as rated.
+        profile.set_power(Power::Lumens(2000.0)).unwrap();
+        assert_eq!(profile.intensity(&Vector3::down(), &Vector3::down()), 200.0);
+        assert_eq!(profile.lumens, 2000.0);
+    }
+}
@@ -0,0 +1,712 @@
+use super::emitters::{emission_pdf, Emitters};
+use super::{
+    beer_lambert, environment, is_linked, medium_after, sample_lights, scattered_ray_kind,
+    spawn_ray, unoccluded, Integrator, EXPOSURE, RAY_COUNT,
+};
+use crate::pbrt::bsdf::{SurfaceBsdf, TransportMode};
+use crate::pbrt::camera::Camera;
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::point::Point;
+use crate::pbrt::rendering::{Intersectable, Ray};
+use crate::pbrt::sampler::{RandomSampler, Sampler};
+use crate::pbrt::scene::{Element, RayKind, Scene};
+use crate::pbrt::vector3::Vector3;
+
+/// Bidirectional path tracer connecting every prefix of a camera subpath with every prefix of a
+/// light subpath, weighting the strategies with multiple importance sampling.
+pub struct BdptIntegrator {
+    pub max_depth: usize,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: usize) -> BdptIntegrator {
+        BdptIntegrator { max_depth }
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let context = Context::new(scene);
+        let mut sampler = RandomSampler::default();
+        let mut film = Film::new(scene.width, scene.height, EXPOSURE);
+        film.set_splat_scale(RAY_COUNT as f32);
+
+        let mut camera_path = Vec::with_capacity(self.max_depth + 2);
+        let mut light_path = Vec::with_capacity(self.max_depth + 1);
+
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                for _ in 0..RAY_COUNT {
+                    camera_path.clear();
+                    light_path.clear();
+
+                    let ray = context.camera.generate_ray(x, y, sampler.get_2d());
+                    generate_camera_subpath(
+                        &context,
+                        ray,
+                        &mut sampler,
+                        self.max_depth + 2,
+                        &mut camera_path,
+                    );
+                    generate_light_subpath(
+                        &context,
+                        &mut sampler,
+                        self.max_depth + 1,
+                        &mut light_path,
+                    );
+
+                    let mut radiance = Color::black();
+                    for t in 1..=camera_path.len() {
+                        // Light sources are sampled at the camera vertices even without emitters.
+                        for s in 0..=light_path.len().max(1) {
+                            let depth = t as i32 + s as i32 - 2;
+                            if (s == 1 && t == 1) || depth < 0 || depth > self.max_depth as i32 {
+                                continue;
+                            }
+
+                            let (contribution, raster) = connect_paths(
+                                &context,
+                                &light_path,
+                                &camera_path,
+                                s,
+                                t,
+                                &mut sampler,
+                            );
+
+                            if t == 1 {
+                                if let Some((raster_x, raster_y)) = raster {
+                                    film.add_splat(raster_x, raster_y, contribution);
+                                }
+                            } else {
+                                radiance = radiance + contribution;
+                            }
+                        }
+                    }
+
+                    film.add_sample(x, y, radiance);
+                }
+            }
+        }
+
+        film
+    }
+}
+
+/// What the subpaths are traced against, shared by every sample of a render.
+pub struct Context<'a> {
+    pub scene: &'a Scene,
+    pub camera: Camera,
+    pub emitters: Emitters<'a>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(scene: &'a Scene) -> Context<'a> {
+        Context {
+            scene,
+            camera: Camera::new(scene),
+            emitters: Emitters::new(scene),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexType {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone, Copy)]
+pub struct Vertex<'a> {
+    kind: VertexType,
+    point: Point,
+    normal: Vector3,
+    element: Option<&'a Element>,
+    // Direction towards the previous vertex of the subpath.
+    wo: Vector3,
+    beta: Color,
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    mode: TransportMode,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(point: Point, beta: Color) -> Vertex<'a> {
+        Vertex {
+            kind: VertexType::Camera,
+            point,
+            normal: Vector3::zero(),
+            element: None,
+            wo: Vector3::zero(),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            mode: TransportMode::Radiance,
+        }
+    }
+
+    fn light(
+        element: &'a Element,
+        point: Point,
+        normal: Vector3,
+        beta: Color,
+        pdf_fwd: f64,
+    ) -> Vertex<'a> {
+        Vertex {
+            kind: VertexType::Light,
+            point,
+            normal,
+            element: Some(element),
+            wo: Vector3::zero(),
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            mode: TransportMode::Importance,
+        }
+    }
+
+    // End of a camera subpath that left the scene along the ray, towards the lights at infinity.
+    fn escape(ray: &Ray, beta: Color) -> Vertex<'a> {
+        Vertex {
+            kind: VertexType::Light,
+            point: ray.origin,
+            normal: Vector3::zero(),
+            element: None,
+            wo: -ray.direction,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            mode: TransportMode::Radiance,
+        }
+    }
+
+    fn surface(
+        element: &'a Element,
+        point: Point,
+        normal: Vector3,
+        wo: Vector3,
+        beta: Color,
+        mode: TransportMode,
+    ) -> Vertex<'a> {
+        Vertex {
+            kind: VertexType::Surface,
+            point,
+            normal,
+            element: Some(element),
+            wo,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            mode,
+        }
+    }
+
+    /// Whether light from the emissive `light` may reach this vertex directly.
+    fn is_lit_by(&self, light: &Element) -> bool {
+        is_linked(self.element, light.attributes().light_group)
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.kind != VertexType::Camera
+    }
+
+    fn is_connectible(&self) -> bool {
+        match (self.kind, self.element) {
+            (VertexType::Surface, Some(element)) => element
+                .bsdf(&self.point)
+                .is_none_or(|bsdf| !bsdf.is_specular()),
+            (VertexType::Light, None) => false,
+            _ => true,
+        }
+    }
+
+    fn f(&self, next: &Vertex) -> Color {
+        let wi = (next.point - self.point).normalize();
+        match self.bsdf() {
+            Some(bsdf) => bsdf.f(&self.wo, &wi),
+            None => Color::black(),
+        }
+    }
+
+    // Scattering at the vertex, for the quantity its subpath carries.
+    fn bsdf(&self) -> Option<SurfaceBsdf> {
+        let mut bsdf = self.element?.bsdf(&self.point)?;
+        bsdf.mode = self.mode;
+        Some(bsdf)
+    }
+
+    /// Radiance emitted from this vertex towards `to`.
+    fn le(&self, context: &Context, to: &Vertex) -> Color {
+        match (self.kind, self.element) {
+            (_, Some(element)) => {
+                element.emitted_towards(&self.point, &self.normal, &(to.point - self.point))
+            }
+            (VertexType::Light, None) => environment(context.scene, to.element, &-self.wo),
+            _ => Color::black(),
+        }
+    }
+
+    /// Converts a solid angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.point - self.point;
+        let distance2 = w.norm();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+
+        let mut pdf = pdf / distance2;
+        if next.is_on_surface() {
+            pdf *= next.normal.dot(&w).abs() / distance2.sqrt();
+        }
+        pdf
+    }
+
+    /// Area density of sampling `next` from this vertex when arriving from `prev`.
+    fn pdf(&self, context: &Context, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        if self.kind == VertexType::Light {
+            return self.pdf_light(next);
+        }
+
+        let wn = (next.point - self.point).normalize();
+        let pdf = match (self.kind, self.element, prev) {
+            (VertexType::Camera, _, _) => context.camera.pdf_direction(&wn),
+            (_, Some(element), Some(prev)) => {
+                let wp = (prev.point - self.point).normalize();
+                element
+                    .bsdf(&self.point)
+                    .map_or(0.0, |bsdf| bsdf.pdf(&wp, &wn))
+            }
+            _ => 0.0,
+        };
+
+        self.convert_density(pdf, next)
+    }
+
+    /// Area density of an emitter at this vertex sending light towards `next`.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let w = next.point - self.point;
+        let distance2 = w.norm();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        let w = w * (1.0 / distance2.sqrt());
+
+        let mut pdf = match self.element {
+            Some(element) => emission_pdf(element, &self.normal, &w) / distance2,
+            None => 0.0,
+        };
+        if next.is_on_surface() {
+            pdf *= next.normal.dot(&w).abs();
+        }
+        pdf
+    }
+
+    /// Area density of a light subpath starting at this vertex.
+    fn pdf_light_origin(&self, context: &Context) -> f64 {
+        match self.element {
+            Some(element) => context.emitters.pdf_emission(element, &self.point),
+            None => 0.0,
+        }
+    }
+
+    /// Area density of the emitter sampling of `receiver` picking this vertex.
+    fn pdf_light_incident(&self, context: &Context, receiver: &Vertex) -> f64 {
+        let element = match self.element {
+            Some(element) if receiver.is_on_surface() => element,
+            _ => return 0.0,
+        };
+
+        let pdf =
+            context
+                .emitters
+                .pdf_incident(&receiver.point, &receiver.normal, element, &self.point);
+        let w = receiver.point - self.point;
+        let distance2 = w.norm();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        pdf * self.normal.dot(&w).abs() / (distance2 * distance2.sqrt())
+    }
+}
+
+pub fn generate_camera_subpath<'a>(
+    context: &Context<'a>,
+    ray: Ray,
+    sampler: &mut dyn Sampler,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) {
+    path.push(Vertex::camera(context.camera.origin(), Color::white()));
+    let pdf_dir = context.camera.pdf_direction(&ray.direction);
+    random_walk(
+        context,
+        ray,
+        Color::white(),
+        pdf_dir,
+        sampler,
+        max_vertices - 1,
+        path,
+    );
+}
+
+pub fn generate_light_subpath<'a>(
+    context: &Context<'a>,
+    sampler: &mut dyn Sampler,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) {
+    let emission = match context.emitters.sample_emission(sampler) {
+        Some(emission) => emission,
+        None => return,
+    };
+
+    path.push(Vertex::light(
+        emission.element,
+        emission.point,
+        emission.normal,
+        emission.radiance,
+        emission.pdf_pos,
+    ));
+
+    let cos_theta = emission.normal.dot(&emission.direction).abs();
+    let beta = emission.radiance * (cos_theta / (emission.pdf_pos * emission.pdf_dir)) as f32;
+    random_walk(
+        context,
+        spawn_ray(&emission.point, &emission.normal, emission.direction),
+        beta,
+        emission.pdf_dir,
+        sampler,
+        max_vertices - 1,
+        path,
+    );
+
+    // Light only carries on from the elements the emitter is linked to.
+    if path.len() > 1 && !path[1].is_lit_by(emission.element) {
+        path.truncate(1);
+    }
+}
+
+fn random_walk<'a>(
+    context: &Context<'a>,
+    mut ray: Ray,
+    mut beta: Color,
+    pdf: f64,
+    sampler: &mut dyn Sampler,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) {
+    let start = path.len();
+    let mut pdf_fwd = pdf;
+    let mut kind = if path[start - 1].is_on_surface() {
+        RayKind::Indirect
+    } else {
+        RayKind::Camera
+    };
+    let mode = path[start - 1].mode;
+    // Subpaths start outside of any dielectric.
+    let mut medium = None;
+
+    while path.len() - start < max_vertices {
+        let intersection = match context.scene.trace_for(&ray, kind) {
+            Some(intersection) => intersection,
+            None => {
+                if mode == TransportMode::Radiance {
+                    path.push(Vertex::escape(&ray, beta));
+                }
+                break;
+            }
+        };
+
+        if let Some(ref absorption) = medium {
+            beta = beta * beer_lambert(absorption, intersection.distance);
+        }
+
+        let element = intersection.element;
+        let point = ray.origin + (ray.direction * intersection.distance);
+        let normal = element.surface_normal(&point);
+        let wo = -ray.direction;
+
+        let prev = path.len() - 1;
+        let mut vertex = Vertex::surface(element, point, normal, wo, beta, mode);
+        vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+        let current = prev + 1;
+
+        if path.len() - start >= max_vertices {
+            break;
+        }
+
+        let bsdf = match path[current].bsdf() {
+            Some(bsdf) => bsdf,
+            None => break,
+        };
+        let sample = match bsdf.sample_f(&wo, sampler) {
+            Some(sample) => sample,
+            None => break,
+        };
+
+        beta = beta * sample.f * (sample.wi.dot(&normal).abs() / sample.pdf) as f32;
+        if beta.is_black() {
+            break;
+        }
+
+        pdf_fwd = if sample.pdf_is_proportional {
+            bsdf.pdf(&wo, &sample.wi)
+        } else {
+            sample.pdf
+        };
+        let mut pdf_rev = bsdf.pdf(&sample.wi, &wo);
+        if sample.specular {
+            path[current].delta = true;
+            pdf_fwd = 0.0;
+            pdf_rev = 0.0;
+        }
+        let rev = path[current].convert_density(pdf_rev, &path[prev]);
+        path[prev].pdf_rev = rev;
+
+        medium = medium_after(element, &normal, &wo, &sample.wi, medium);
+        kind = scattered_ray_kind(&sample, &normal, &wo);
+        ray = spawn_ray(&point, &normal, sample.wi);
+    }
+}
+
+fn geometry_term(context: &Context, a: &Vertex, b: &Vertex) -> f64 {
+    let d = a.point - b.point;
+    let distance2 = d.norm();
+    let w = d * (1.0 / distance2.sqrt());
+
+    let mut g = 1.0 / distance2;
+    if a.is_on_surface() {
+        g *= a.normal.dot(&w).abs();
+    }
+    if b.is_on_surface() {
+        g *= b.normal.dot(&w).abs();
+    }
+
+    if unoccluded(context.scene, &a.point, &a.normal, &b.point) {
+        g
+    } else {
+        0.0
+    }
+}
+
+/// Contribution of the strategy using `s` light and `t` camera vertices, with the pixel to splat
+/// it to when the camera was resampled (`t == 1`).
+pub fn connect_paths<'a>(
+    context: &Context<'a>,
+    light_path: &[Vertex<'a>],
+    camera_path: &[Vertex<'a>],
+    s: usize,
+    t: usize,
+    sampler: &mut dyn Sampler,
+) -> (Color, Option<(u32, u32)>) {
+    let mut radiance = Color::black();
+    // Light from the light sources, which no other strategy reaches.
+    let mut unweighted = Color::black();
+    let mut sampled = None;
+    let mut raster = None;
+
+    if s == 0 {
+        let pt = &camera_path[t - 1];
+        let prev = &camera_path[t - 2];
+        let seen = match pt.element {
+            Some(element) => prev.is_lit_by(element),
+            // Lights at infinity were already sampled from the non-specular vertices.
+            None => !prev.is_on_surface() || prev.delta,
+        };
+        if seen {
+            radiance = pt.le(context, prev) * pt.beta;
+        }
+    } else if t == 1 {
+        let qs = &light_path[s - 1];
+        if qs.is_connectible() {
+            if let Some(position) = context.camera.raster_position(&qs.point) {
+                let camera_point = context.camera.origin();
+                let to_point = qs.point - camera_point;
+                let distance2 = to_point.norm();
+                let direction = to_point * (1.0 / distance2.sqrt());
+                let cos_theta = direction.dot(&context.camera.forward());
+                let importance = context.camera.importance(&direction);
+
+                if importance > 0.0 && cos_theta > 0.0 {
+                    // Density of picking the pinhole, as a solid angle measured from `qs`.
+                    let pdf = distance2 / cos_theta;
+                    let camera =
+                        Vertex::camera(camera_point, Color::white() * (importance / pdf) as f32);
+
+                    radiance = qs.beta * qs.f(&camera) * camera.beta;
+                    if qs.is_on_surface() {
+                        radiance = radiance * qs.normal.dot(&direction).abs() as f32;
+                    }
+                    if !radiance.is_black()
+                        && !unoccluded(context.scene, &qs.point, &qs.normal, &camera_point)
+                    {
+                        radiance = Color::black();
+                    }
+
+                    sampled = Some(camera);
+                    raster = Some(position);
+                }
+            }
+        }
+    } else if s == 1 {
+        let pt = &camera_path[t - 1];
+        if let (true, Some(element), Some(bsdf)) = (pt.is_connectible(), pt.element, pt.bsdf()) {
+            let lights = sample_lights(context.scene, element, &pt.point, &bsdf, &pt.wo, sampler);
+            unweighted = pt.beta * lights;
+        }
+        if pt.is_connectible() {
+            if let Some(sample) = context
+                .emitters
+                .sample_incident(&pt.point, &pt.normal, sampler)
+                .filter(|sample| pt.is_lit_by(sample.element))
+            {
+                let mut light_vertex = Vertex::light(
+                    sample.element,
+                    sample.point,
+                    sample.normal,
+                    sample.radiance * (1.0 / sample.pdf) as f32,
+                    0.0,
+                );
+                // Kept as for light subpaths, `mis_weight` accounts for how it was sampled.
+                light_vertex.pdf_fwd = light_vertex.pdf_light_origin(context);
+
+                let direction = (sample.point - pt.point).normalize();
+                radiance = pt.beta * pt.f(&light_vertex) * light_vertex.beta;
+                if pt.is_on_surface() {
+                    radiance = radiance * pt.normal.dot(&direction).abs() as f32;
+                }
+                if !radiance.is_black()
+                    && !unoccluded(context.scene, &pt.point, &pt.normal, &sample.point)
+                {
+                    radiance = Color::black();
+                }
+
+                sampled = Some(light_vertex);
+            }
+        }
+    } else {
+        let qs = &light_path[s - 1];
+        let pt = &camera_path[t - 1];
+        if qs.is_connectible() && pt.is_connectible() {
+            radiance = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+            if !radiance.is_black() {
+                radiance = radiance * geometry_term(context, qs, pt) as f32;
+            }
+        }
+    }
+
+    if radiance.is_black() {
+        return (unweighted, raster);
+    }
+
+    let weight = mis_weight(context, light_path, camera_path, sampled, s, t);
+    (radiance * weight as f32 + unweighted, raster)
+}
+
+fn mis_weight<'a>(
+    context: &Context<'a>,
+    light_path: &[Vertex<'a>],
+    camera_path: &[Vertex<'a>],
+    sampled: Option<Vertex<'a>>,
+    s: usize,
+    t: usize,
+) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    // Work on copies updated as if the current strategy had generated the whole path.
+    let mut light: Vec<Vertex> = match sampled {
+        Some(vertex) if s == 1 => vec![vertex],
+        _ => light_path[..s].to_vec(),
+    };
+    let mut camera: Vec<Vertex> = match sampled {
+        Some(vertex) if t == 1 => vec![vertex],
+        _ => camera_path[..t].to_vec(),
+    };
+
+    if s == 0 && camera[t - 1].pdf_light_origin(context) == 0.0 {
+        // Emitters that can't be sampled are only ever found by the camera subpath.
+        return 1.0;
+    }
+
+    // The densities below start every light subpath like `sample_emission`, while the strategy
+    // with a single light vertex samples it from its neighbour, through the light BVH.
+    let (origin, receiver) = match s {
+        0 => (&camera[t - 1], &camera[t - 2]),
+        1 => (&light[0], &camera[t - 1]),
+        _ => (&light[0], &light[1]),
+    };
+    let emission = origin.pdf_light_origin(context);
+    let incident = if emission > 0.0 {
+        origin.pdf_light_incident(context, receiver) / emission
+    } else {
+        0.0
+    };
+    let relative = |strategy: usize| if strategy == 1 { incident } else { 1.0 };
+
+    // The connection vertices are non-degenerate for this strategy.
+    camera[t - 1].delta = false;
+    if s > 0 {
+        light[s - 1].delta = false;
+    }
+
+    camera[t - 1].pdf_rev = if s > 0 {
+        let qs_minus = if s > 1 { Some(&light[s - 2]) } else { None };
+        light[s - 1].pdf(context, qs_minus, &camera[t - 1])
+    } else {
+        camera[t - 1].pdf_light_origin(context)
+    };
+
+    if t > 1 {
+        let pdf = if s > 0 {
+            camera[t - 1].pdf(context, Some(&light[s - 1]), &camera[t - 2])
+        } else {
+            camera[t - 1].pdf_light(&camera[t - 2])
+        };
+        camera[t - 2].pdf_rev = pdf;
+    }
+
+    if s > 0 {
+        let pt_minus = if t > 1 { Some(&camera[t - 2]) } else { None };
+        light[s - 1].pdf_rev = camera[t - 1].pdf(context, pt_minus, &light[s - 1]);
+    }
+
+    if s > 1 {
+        let pdf = light[s - 1].pdf(context, Some(&camera[t - 1]), &light[s - 2]);
+        light[s - 2].pdf_rev = pdf;
+    }
+
+    let remap = |f: f64| if f != 0.0 { f } else { 1.0 };
+    let mut sum_ri = 0.0;
+
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        ri *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum_ri += ri * relative(s + t - i);
+        }
+    }
+
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let delta_light_vertex = i > 0 && light[i - 1].delta;
+        if !light[i].delta && !delta_light_vertex {
+            sum_ri += ri * relative(i);
+        }
+    }
+
+    let own = relative(s);
+    if own == 0.0 {
+        return 0.0;
+    }
+    own / (own + sum_ri)
+}
@@ -0,0 +1,179 @@
+use super::{face_forward, spawn_ray, Integrator};
+use crate::pbrt::bsdf::fresnel_conductor;
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::rendering::{Intersectable, Ray};
+use crate::pbrt::sampler::{RandomSampler, Sampler};
+use crate::pbrt::sampling::{cosine_sample_hemisphere, to_world};
+use crate::pbrt::scene::{Material, RayKind, Scene, TextureCoords};
+
+/// What a `DebugIntegrator` writes for the first surface seen through each pixel.
+pub enum DebugChannel {
+    /// Surface normal, remapped from `[-1, 1]` to `[0, 1]`.
+    Normals,
+    /// Hit distance, scaled so the farthest hit is white.
+    Depth,
+    /// Fractional part of the texture coordinates in red and green.
+    TextureCoords,
+    /// Reflectance of the material.
+    Albedo,
+    /// Color derived from the index of the element in the scene.
+    ElementId,
+    /// Fraction of cosine distributed rays not hitting anything closer than `radius`.
+    AmbientOcclusion { radius: f64, samples: u32 },
+}
+
+pub struct DebugIntegrator {
+    pub channel: DebugChannel,
+}
+
+impl DebugIntegrator {
+    pub fn new(channel: DebugChannel) -> DebugIntegrator {
+        DebugIntegrator { channel }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let mut sampler = RandomSampler::default();
+        let mut film = Film::new(scene.width, scene.height, 1.0);
+
+        let mut values = Vec::with_capacity((scene.width * scene.height) as usize);
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                values.push(self.evaluate(scene, x, y, &mut sampler));
+            }
+        }
+
+        if let DebugChannel::Depth = self.channel {
+            let max_depth = values.iter().fold(0.0f32, |acc, c| acc.max(c.r));
+            if max_depth > 0.0 {
+                for value in values.iter_mut() {
+                    *value = *value * (1.0 / max_depth);
+                }
+            }
+        }
+
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                film.add_sample(x, y, values[(y * scene.width + x) as usize]);
+            }
+        }
+
+        film
+    }
+}
+
+impl DebugIntegrator {
+    fn evaluate(&self, scene: &Scene, x: u32, y: u32, sampler: &mut dyn Sampler) -> Color {
+        let ray = Ray::create_prime(x, y, scene);
+        let intersection = match scene.trace_for(&ray, RayKind::Camera) {
+            Some(intersection) => intersection,
+            None => return Color::black(),
+        };
+
+        let element = intersection.element;
+        let hit_point = ray.origin + (ray.direction * intersection.distance);
+        let normal = element.surface_normal(&hit_point);
+
+        match self.channel {
+            DebugChannel::Normals => Color {
+                r: ((normal.x + 1.0) * 0.5) as f32,
+                g: ((normal.y + 1.0) * 0.5) as f32,
+                b: ((normal.z + 1.0) * 0.5) as f32,
+            },
+
+            DebugChannel::Depth => {
+                let depth = intersection.distance as f32;
+                Color {
+                    r: depth,
+                    g: depth,
+                    b: depth,
+                }
+            }
+
+            DebugChannel::TextureCoords => {
+                let texture_coords = element.texture_coords(&hit_point);
+                Color {
+                    r: texture_coords.x - texture_coords.x.floor(),
+                    g: texture_coords.y - texture_coords.y.floor(),
+                    b: 0.0,
+                }
+            }
+
+            DebugChannel::Albedo => albedo(element.material(), &element.texture_coords(&hit_point)),
+
+            DebugChannel::ElementId => {
+                let index = scene
+                    .entities
+                    .iter()
+                    .position(|e| std::ptr::eq(e, element))
+                    .unwrap_or(0);
+                id_color(index)
+            }
+
+            DebugChannel::AmbientOcclusion { radius, samples } => {
+                let facing_normal = face_forward(&normal, &-ray.direction);
+
+                let mut unoccluded = 0;
+                for _ in 0..samples {
+                    let direction =
+                        to_world(&cosine_sample_hemisphere(sampler.get_2d()), &facing_normal);
+                    let occluded = scene
+                        .trace_for(
+                            &spawn_ray(&hit_point, &facing_normal, direction),
+                            RayKind::Shadow,
+                        )
+                        .is_some_and(|hit| hit.distance < radius);
+                    if !occluded {
+                        unoccluded += 1;
+                    }
+                }
+
+                let ao = unoccluded as f32 / samples.max(1) as f32;
+                Color {
+                    r: ao,
+                    g: ao,
+                    b: ao,
+                }
+            }
+        }
+    }
+}
+
+// Reflectance of `material`, seen through the coat for coated ones.
+fn albedo(material: &Material, texture_coords: &TextureCoords) -> Color {
+    match *material {
+        Material::Diffuse {
+            ref color, albedo, ..
+        } => color.color(texture_coords) * albedo * std::f32::consts::PI,
+        Material::Emissive { ref emission, .. } => emission.color(texture_coords),
+        Material::Conductor {
+            ref eta,
+            ref k,
+            film,
+            ..
+        } => match film {
+            Some(film) => film.conductor(1.0, eta, k),
+            None => fresnel_conductor(1.0, eta, k),
+        },
+        Material::Principled { ref base_color, .. } => base_color.color(texture_coords),
+        Material::Coated { ref base, .. } => albedo(base, texture_coords),
+        Material::Subsurface { albedo, .. } => albedo,
+        Material::Reflective | Material::Refractive { .. } => Color::white(),
+    }
+}
+
+// Scrambles the index so neighbouring elements get clearly different colors.
+fn id_color(index: usize) -> Color {
+    let mut hash = (index as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+
+    Color {
+        r: (hash & 0xFF) as f32 / 255.0,
+        g: ((hash >> 8) & 0xFF) as f32 / 255.0,
+        b: ((hash >> 16) & 0xFF) as f32 / 255.0,
+    }
+}
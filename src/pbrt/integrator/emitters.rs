@@ -0,0 +1,846 @@
+use image::GenericImage;
+use std::collections::HashMap;
+
+use super::light_bvh::{LightBounds, LightBvh};
+use super::FLOATING_POINT_BACKOFF;
+use crate::pbrt::color::Color;
+use crate::pbrt::point::Point;
+use crate::pbrt::rendering::Intersectable;
+use crate::pbrt::sampler::Sampler;
+use crate::pbrt::sampling::{
+    cosine_hemisphere_pdf, cosine_sample_hemisphere, create_coordinate_system,
+    direction_to_equirect, sample_equirect, to_world, uniform_sample_triangle, Distribution1D,
+    Distribution2D,
+};
+use crate::pbrt::scene::{
+    barycentric, Coloration, Element, Material, Plane, Scene, Sphere, Texture, TextureCoords,
+};
+use crate::pbrt::vector3::Vector3;
+
+const BOUNDS_EPSILON: f64 = 1e-4;
+
+// Finest subdivision of each edge of a triangle when following the texture of its emission.
+const MAX_SUBDIVISIONS: usize = 32;
+
+// Share of the average luminance every cell keeps, so that texels between the lookups of a
+// cell still get sampled.
+const CELL_FLOOR: f64 = 0.05;
+
+/// Where on the surface of a textured emitter points are picked.
+enum EmissionDistribution {
+    /// Texels of a sphere, weighted by their luminance and the area they cover.
+    Texels(Distribution2D),
+    /// Cells of a regular subdivision of every triangle, weighted by luminance and area.
+    Cells {
+        subdivisions: usize,
+        cells: Distribution1D,
+    },
+}
+
+/// An emissive element acting as a light. Planes are unbounded, so only the patch of the plane
+/// inside the scene bounds is sampled. Textured emission is sampled proportionally to its
+/// luminance.
+pub struct AreaLight<'a> {
+    pub element: &'a Element,
+    pub area: f64,
+    triangles: Vec<[Point; 3]>,
+    triangle_areas: Vec<f64>,
+    distribution: Option<EmissionDistribution>,
+}
+
+impl<'a> AreaLight<'a> {
+    fn new(element: &'a Element, bounds: &(Point, Point)) -> Option<AreaLight<'a>> {
+        let triangles: Vec<[Point; 3]> = match *element {
+            Element::Sphere(_) => vec![],
+            Element::Plane(ref plane) => {
+                let polygon = clip_plane(plane, bounds);
+                if polygon.len() < 3 {
+                    return None;
+                }
+
+                polygon[1..]
+                    .windows(2)
+                    .map(|w| [polygon[0], w[0], w[1]])
+                    .collect()
+            }
+            Element::Polygon(ref p) => {
+                vec![[
+                    Point::new(p.vertices[0].x, p.vertices[0].y, p.vertices[0].z),
+                    Point::new(p.vertices[1].x, p.vertices[1].y, p.vertices[1].z),
+                    Point::new(p.vertices[2].x, p.vertices[2].y, p.vertices[2].z),
+                ]]
+            }
+        };
+        let triangle_areas: Vec<f64> = triangles
+            .iter()
+            .map(|t| (t[1] - t[0]).cross(&(t[2] - t[0])).length() * 0.5)
+            .collect();
+        let area = if triangles.is_empty() {
+            element.area()?
+        } else {
+            triangle_areas.iter().sum()
+        };
+
+        let mut light = AreaLight {
+            element,
+            area,
+            triangles,
+            triangle_areas,
+            distribution: None,
+        };
+        if let Material::Emissive {
+            emission: Coloration::Texture(ref texture),
+            ..
+        } = *element.material()
+        {
+            light.distribution = Some(match *element {
+                Element::Sphere(_) => light.texel_distribution(texture),
+                _ => light.cell_distribution(texture),
+            });
+        }
+
+        Some(light)
+    }
+
+    fn texel_distribution(&self, texture: &Texture) -> EmissionDistribution {
+        let (width, height) = texture.texture.dimensions();
+        let func: Vec<f64> = (0..height)
+            .flat_map(|y| {
+                // Rows near the poles cover less of the sphere.
+                let sin_theta = (std::f64::consts::PI * (y as f64 + 0.5) / height as f64).sin();
+                (0..width).map(move |x| {
+                    let color = Color::from_rgba(texture.texture.get_pixel(x, y));
+                    color.luminance() as f64 * sin_theta
+                })
+            })
+            .collect();
+
+        EmissionDistribution::Texels(Distribution2D::new(&func, width as usize, height as usize))
+    }
+
+    fn cell_distribution(&self, texture: &Texture) -> EmissionDistribution {
+        // Roughly one cell per texel the longest edge spans, within limits.
+        let (width, height) = texture.texture.dimensions();
+        let resolution = width.max(height) as f64;
+        let span = self
+            .triangles
+            .iter()
+            .flat_map(|t| {
+                let coords: Vec<TextureCoords> =
+                    t.iter().map(|p| self.element.texture_coords(p)).collect();
+                (0..3).map(move |i| {
+                    let (a, b) = (coords[i], coords[(i + 1) % 3]);
+                    ((a.x - b.x) as f64).hypot((a.y - b.y) as f64)
+                })
+            })
+            .fold(0.0, f64::max);
+        let subdivisions = ((span * resolution).ceil() as usize).clamp(1, MAX_SUBDIVISIONS);
+
+        let luminance = |p: Point| self.element.emitted(&p).luminance() as f64;
+        let mut func = vec![0.0; self.triangles.len() * 2 * subdivisions * subdivisions];
+        for (t, triangle) in self.triangles.iter().enumerate() {
+            for upper in 0..2 {
+                for i in 0..subdivisions {
+                    for j in 0..subdivisions - i - upper {
+                        let corners = cell_corners(triangle, subdivisions, i, j, upper == 1);
+                        let centroid = corners[0]
+                            + ((corners[1] - corners[0]) * (1.0 / 3.0))
+                            + ((corners[2] - corners[0]) * (1.0 / 3.0));
+                        let total = corners.iter().fold(luminance(centroid), |acc, c| {
+                            acc + luminance(centroid + (*c - centroid) * 0.5)
+                        });
+                        func[cell_index(t, subdivisions, i, j, upper == 1)] = total * 0.25;
+                    }
+                }
+            }
+        }
+
+        // All cells of a triangle have the same area.
+        let valid = self.triangles.len() * subdivisions * subdivisions;
+        let average = func.iter().sum::<f64>() / valid as f64;
+        let cell_count = 2 * subdivisions * subdivisions;
+        for (index, value) in func.iter_mut().enumerate() {
+            let (i, j, upper) = cell_position(index % cell_count, subdivisions);
+            if i + j + upper as usize >= subdivisions {
+                continue;
+            }
+            let cell_area =
+                self.triangle_areas[index / cell_count] / (subdivisions * subdivisions) as f64;
+            *value = (*value + CELL_FLOOR * average) * cell_area;
+        }
+
+        EmissionDistribution::Cells {
+            subdivisions,
+            cells: Distribution1D::new(&func),
+        }
+    }
+
+    /// Point on the emitting surface, its normal and area density.
+    pub fn sample_point(&self, u: (f64, f64)) -> (Point, Vector3, f64) {
+        match self.distribution {
+            Some(EmissionDistribution::Texels(ref texels)) => {
+                let sphere = match *self.element {
+                    Element::Sphere(ref sphere) => sphere,
+                    _ => unreachable!(),
+                };
+
+                // The sphere is textured like an equirectangular map turned by half a turn.
+                match sample_equirect(texels, u, std::f64::consts::PI) {
+                    Some((normal, _, pdf)) => (
+                        sphere.center + normal * sphere.radius,
+                        normal,
+                        pdf / (sphere.radius * sphere.radius),
+                    ),
+                    None => (sphere.center, Vector3::up(), 0.0),
+                }
+            }
+
+            Some(EmissionDistribution::Cells {
+                subdivisions,
+                ref cells,
+            }) => {
+                let (x, _, index) = cells.sample_continuous(u.0);
+                let u0 = (x * cells.count() as f64 - index as f64).clamp(0.0, 1.0);
+
+                let cell_count = 2 * subdivisions * subdivisions;
+                let triangle = index / cell_count;
+                let (i, j, upper) = cell_position(index % cell_count, subdivisions);
+                let corners = cell_corners(&self.triangles[triangle], subdivisions, i, j, upper);
+                let point = sample_triangle(&corners, (u0, u.1));
+                let cell_area =
+                    self.triangle_areas[triangle] / (subdivisions * subdivisions) as f64;
+
+                (
+                    point,
+                    self.element.surface_normal(&point),
+                    cells.discrete_pdf(index) / cell_area,
+                )
+            }
+
+            None => {
+                if self.triangles.is_empty() {
+                    let (point, normal) = self.element.sample_point(u).unwrap();
+                    return (point, normal, 1.0 / self.area);
+                }
+
+                // Pick a triangle by area and reuse what's left of `u.0` inside it.
+                let mut remaining = u.0 * self.area;
+                let mut index = self.triangles.len() - 1;
+                for (i, area) in self.triangle_areas.iter().enumerate() {
+                    if remaining < *area {
+                        index = i;
+                        break;
+                    }
+                    remaining -= area;
+                }
+                let u0 = (remaining / self.triangle_areas[index]).min(1.0);
+                let point = sample_triangle(&self.triangles[index], (u0, u.1));
+
+                (point, self.element.surface_normal(&point), 1.0 / self.area)
+            }
+        }
+    }
+
+    /// Area density of `sample_point` picking `point`, which lies on the surface.
+    pub fn pdf_point(&self, point: &Point) -> f64 {
+        match self.distribution {
+            Some(EmissionDistribution::Texels(ref texels)) => {
+                let sphere = match *self.element {
+                    Element::Sphere(ref sphere) => sphere,
+                    _ => unreachable!(),
+                };
+
+                let normal = (*point - sphere.center).normalize();
+                let uv = direction_to_equirect(&normal, std::f64::consts::PI);
+                let sin_theta = (uv.1 * std::f64::consts::PI).sin();
+                if sin_theta == 0.0 {
+                    return 0.0;
+                }
+
+                texels.pdf(uv)
+                    / (2.0
+                        * std::f64::consts::PI
+                        * std::f64::consts::PI
+                        * sin_theta
+                        * sphere.radius
+                        * sphere.radius)
+            }
+
+            Some(EmissionDistribution::Cells {
+                subdivisions,
+                ref cells,
+            }) => {
+                for (t, triangle) in self.triangles.iter().enumerate() {
+                    let (b1, b2) = barycentric(
+                        &(triangle[1] - triangle[0]),
+                        &(triangle[2] - triangle[0]),
+                        &(*point - triangle[0]),
+                    );
+                    if b1 < -BOUNDS_EPSILON
+                        || b2 < -BOUNDS_EPSILON
+                        || b1 + b2 > 1.0 + BOUNDS_EPSILON
+                    {
+                        continue;
+                    }
+
+                    let n = subdivisions as f64;
+                    let i = ((b1 * n).max(0.0) as usize).min(subdivisions - 1);
+                    let j = ((b2 * n).max(0.0) as usize).min(subdivisions - 1 - i);
+                    let upper =
+                        b1 * n - i as f64 + b2 * n - j as f64 > 1.0 && i + j + 1 < subdivisions;
+                    let cell_area = self.triangle_areas[t] / (subdivisions * subdivisions) as f64;
+
+                    return cells.discrete_pdf(cell_index(t, subdivisions, i, j, upper))
+                        / cell_area;
+                }
+
+                0.0
+            }
+
+            None => 1.0 / self.area,
+        }
+    }
+
+    /// Box around the emitting surface, the cone its normals lie in and its power.
+    fn light_bounds(&self) -> LightBounds {
+        let material = self.element.material();
+        let two_sided = material.is_two_sided();
+        let sides = if two_sided { 2.0 } else { 1.0 };
+        let power =
+            material.average_emitted_luminance() as f64 * std::f64::consts::PI * sides * self.area;
+
+        let (points, axis, cos_theta_o) = match *self.element {
+            Element::Sphere(ref s) => (
+                vec![
+                    s.center - Vector3::from_one(s.radius),
+                    s.center + Vector3::from_one(s.radius),
+                ],
+                Vector3::up(),
+                -1.0,
+            ),
+            Element::Plane(ref p) => (
+                self.triangles
+                    .iter()
+                    .flat_map(|t| t.iter().cloned())
+                    .collect(),
+                -p.normal.normalize(),
+                1.0,
+            ),
+            Element::Polygon(ref p) => (
+                self.triangles
+                    .iter()
+                    .flat_map(|t| t.iter().cloned())
+                    .collect(),
+                p.normal.normalize(),
+                1.0,
+            ),
+        };
+
+        let mut min = Point::from_one(f64::INFINITY);
+        let mut max = Point::from_one(f64::NEG_INFINITY);
+        for p in &points {
+            min = Point::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+
+        LightBounds {
+            min,
+            max,
+            power,
+            axis,
+            cos_theta_o,
+            // Lambertian emitters send light out over the whole hemisphere around the normal.
+            cos_theta_e: 0.0,
+            two_sided,
+        }
+    }
+
+    /// Point of the light as seen from `receiver` with its normal and solid angle density.
+    /// Uniformly colored spheres only pick from the cone they subtend, everything else is
+    /// sampled by area.
+    pub fn sample_from(&self, receiver: &Point, u: (f64, f64)) -> Option<(Point, Vector3, f64)> {
+        if let (Element::Sphere(ref sphere), None) = (self.element, &self.distribution) {
+            if let Some(sample) = sample_sphere_cone(sphere, receiver, u) {
+                return Some(sample);
+            }
+        }
+
+        let (point, normal, pdf) = self.sample_point(u);
+        let to_receiver = *receiver - point;
+        let distance2 = to_receiver.norm();
+        let mut cos_light = normal.dot(&to_receiver) / distance2.sqrt();
+        if self.element.material().is_two_sided() {
+            cos_light = cos_light.abs();
+        }
+        if cos_light <= 0.0 || pdf == 0.0 {
+            return None;
+        }
+
+        Some((point, normal, pdf * distance2 / cos_light))
+    }
+
+    /// Solid angle density of `sample_from` picking `point` on the light as seen from
+    /// `receiver`.
+    pub fn pdf_from(&self, receiver: &Point, point: &Point) -> f64 {
+        if let (Element::Sphere(ref sphere), None) = (self.element, &self.distribution) {
+            if let Some(cos_max) = sphere_cone(sphere, receiver) {
+                return 1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_max));
+            }
+        }
+
+        let to_receiver = *receiver - *point;
+        let distance2 = to_receiver.norm();
+        let mut cos_light = self.element.surface_normal(point).dot(&to_receiver) / distance2.sqrt();
+        if self.element.material().is_two_sided() {
+            cos_light = cos_light.abs();
+        }
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+
+        self.pdf_point(point) * distance2 / cos_light
+    }
+}
+
+// Position of a cell inside its triangle from its index there.
+fn cell_position(index: usize, subdivisions: usize) -> (usize, usize, bool) {
+    let upper = index >= subdivisions * subdivisions;
+    let index = index % (subdivisions * subdivisions);
+    (index / subdivisions, index % subdivisions, upper)
+}
+
+fn cell_index(triangle: usize, subdivisions: usize, i: usize, j: usize, upper: bool) -> usize {
+    let cells = subdivisions * subdivisions;
+    triangle * 2 * cells + if upper { cells } else { 0 } + i * subdivisions + j
+}
+
+// Corners of cell `(i, j)` of a triangle split `subdivisions` times along each edge, counted
+// along its second and third vertex. Upper cells point the other way and fill the gaps.
+fn cell_corners(
+    triangle: &[Point; 3],
+    subdivisions: usize,
+    i: usize,
+    j: usize,
+    upper: bool,
+) -> [Point; 3] {
+    let n = subdivisions as f64;
+    let e1 = triangle[1] - triangle[0];
+    let e2 = triangle[2] - triangle[0];
+    let at = |a: usize, b: usize| triangle[0] + e1 * (a as f64 / n) + e2 * (b as f64 / n);
+
+    if upper {
+        [at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)]
+    } else {
+        [at(i, j), at(i + 1, j), at(i, j + 1)]
+    }
+}
+
+// Point uniformly distributed over a triangle.
+fn sample_triangle(triangle: &[Point; 3], u: (f64, f64)) -> Point {
+    let (b0, b1) = uniform_sample_triangle(u);
+    triangle[0] + (triangle[1] - triangle[0]) * b1 + (triangle[2] - triangle[0]) * (1.0 - b0 - b1)
+}
+
+/// Cosine of the half-angle of the cone the sphere subtends from `receiver`, `None` when the
+/// receiver is inside it or on its surface.
+fn sphere_cone(sphere: &Sphere, receiver: &Point) -> Option<f64> {
+    let distance2 = (sphere.center - *receiver).norm();
+    let outer_radius = sphere.radius + FLOATING_POINT_BACKOFF;
+    if distance2 <= outer_radius * outer_radius {
+        return None;
+    }
+
+    let sin_max2 = sphere.radius * sphere.radius / distance2;
+    Some((1.0 - sin_max2).max(0.0).sqrt())
+}
+
+/// Uniformly samples the cone of directions from `receiver` towards the sphere, `None` when the
+/// receiver is inside it or on its surface.
+fn sample_sphere_cone(
+    sphere: &Sphere,
+    receiver: &Point,
+    u: (f64, f64),
+) -> Option<(Point, Vector3, f64)> {
+    let cos_max = sphere_cone(sphere, receiver)?;
+    let to_center = sphere.center - *receiver;
+    let distance2 = to_center.norm();
+    let radius2 = sphere.radius * sphere.radius;
+    let distance = distance2.sqrt();
+    let cos_theta = (1.0 - u.0) + u.0 * cos_max;
+    let sin_theta2 = (1.0 - cos_theta * cos_theta).max(0.0);
+    let phi = u.1 * 2.0 * std::f64::consts::PI;
+
+    // Angle at the center between the receiver and the sampled point.
+    let distance_surface =
+        distance * cos_theta - (radius2 - distance2 * sin_theta2).max(0.0).sqrt();
+    let cos_alpha = (distance2 + radius2 - distance_surface * distance_surface)
+        / (2.0 * distance * sphere.radius);
+    let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+
+    // Local frame with y pointing from the center back to the receiver.
+    let local = Vector3 {
+        x: sin_alpha * phi.cos(),
+        y: cos_alpha,
+        z: sin_alpha * phi.sin(),
+    };
+    let normal = to_world(&local, &(to_center * (-1.0 / distance)));
+    let point = sphere.center + normal * sphere.radius;
+
+    Some((
+        point,
+        normal,
+        1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_max)),
+    ))
+}
+
+/// Solid angle density of an emitter at `element` sending light along `direction`.
+pub fn emission_pdf(element: &Element, normal: &Vector3, direction: &Vector3) -> f64 {
+    let cos_theta = normal.dot(direction);
+    if element.material().is_two_sided() {
+        0.5 * cosine_hemisphere_pdf(cos_theta.abs())
+    } else {
+        cosine_hemisphere_pdf(cos_theta)
+    }
+}
+
+pub struct IncidentSample<'a> {
+    pub element: &'a Element,
+    pub point: Point,
+    pub normal: Vector3,
+    pub radiance: Color,
+    // Solid angle density at the receiver, including the choice of the emitter.
+    pub pdf: f64,
+}
+
+pub struct EmissionSample<'a> {
+    pub element: &'a Element,
+    pub point: Point,
+    pub normal: Vector3,
+    pub direction: Vector3,
+    pub radiance: Color,
+    // Area density of the point, including the choice of the emitter.
+    pub pdf_pos: f64,
+    pub pdf_dir: f64,
+}
+
+/// Area lights of all the emissive elements of the scene. Light subpaths start on one chosen
+/// uniformly, while lights sampled from a receiver are chosen through a light BVH.
+pub struct Emitters<'a> {
+    emitters: Vec<AreaLight<'a>>,
+    // Position in `emitters` by address of the element.
+    indices: HashMap<usize, usize>,
+    bvh: LightBvh,
+    bounds: (Point, Point),
+}
+
+impl<'a> Emitters<'a> {
+    pub fn new(scene: &'a Scene) -> Emitters<'a> {
+        let bounds = scene.bounds();
+        let emitters: Vec<AreaLight<'a>> = scene
+            .entities
+            .iter()
+            .filter(|e| e.material().is_emissive())
+            .filter_map(|e| AreaLight::new(e, &bounds))
+            .filter(|e| e.area > 0.0)
+            .collect();
+        let light_bounds: Vec<LightBounds> = emitters.iter().map(|e| e.light_bounds()).collect();
+        let indices = emitters
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (element_key(e.element), i))
+            .collect();
+
+        Emitters {
+            bvh: LightBvh::new(&light_bounds),
+            indices,
+            emitters,
+            bounds,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    /// Box around the scene, which unbounded emitters are clipped to.
+    pub fn bounds(&self) -> &(Point, Point) {
+        &self.bounds
+    }
+
+    pub fn sample(&self, u: f64) -> Option<(&AreaLight<'a>, f64)> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+
+        let count = self.emitters.len();
+        let index = ((u * count as f64) as usize).min(count - 1);
+        Some((&self.emitters[index], 1.0 / count as f64))
+    }
+
+    /// Area density of `sample_emission` starting at `point` on `element`.
+    pub fn pdf_emission(&self, element: &Element, point: &Point) -> f64 {
+        match self.find(element, point) {
+            Some((_, emitter)) => emitter.pdf_point(point) / self.emitters.len() as f64,
+            None => 0.0,
+        }
+    }
+
+    /// Solid angle density of `sample_incident` picking `point` on `element` from `receiver`,
+    /// a surface with `normal`.
+    pub fn pdf_incident(
+        &self,
+        receiver: &Point,
+        normal: &Vector3,
+        element: &Element,
+        point: &Point,
+    ) -> f64 {
+        match self.find(element, point) {
+            Some((index, emitter)) => {
+                self.bvh.pmf(receiver, normal, index) * emitter.pdf_from(receiver, point)
+            }
+            None => 0.0,
+        }
+    }
+
+    // Area light of `element` and its index, when `point` is on the part of it that is sampled.
+    fn find(&self, element: &Element, point: &Point) -> Option<(usize, &AreaLight<'a>)> {
+        let index = *self.indices.get(&element_key(element))?;
+        if let Element::Plane(_) = *element {
+            if !self.contains(point) {
+                return None;
+            }
+        }
+
+        Some((index, &self.emitters[index]))
+    }
+
+    fn contains(&self, point: &Point) -> bool {
+        let (min, max) = self.bounds;
+        point.x >= min.x - BOUNDS_EPSILON
+            && point.y >= min.y - BOUNDS_EPSILON
+            && point.z >= min.z - BOUNDS_EPSILON
+            && point.x <= max.x + BOUNDS_EPSILON
+            && point.y <= max.y + BOUNDS_EPSILON
+            && point.z <= max.z + BOUNDS_EPSILON
+    }
+
+    /// Samples a point on an emitter as seen from `receiver`, a surface with `normal`. Emitters
+    /// that are bright, close and facing the receiver are picked more often.
+    pub fn sample_incident(
+        &self,
+        receiver: &Point,
+        normal: &Vector3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<IncidentSample<'a>> {
+        let (index, light_pdf) = self.bvh.sample(receiver, normal, sampler.get_1d())?;
+        let emitter = &self.emitters[index];
+        let (point, normal, pdf) = emitter.sample_from(receiver, sampler.get_2d())?;
+
+        Some(IncidentSample {
+            element: emitter.element,
+            point,
+            normal,
+            radiance: emitter
+                .element
+                .emitted_towards(&point, &normal, &(*receiver - point)),
+            pdf: light_pdf * pdf,
+        })
+    }
+
+    /// Samples a point on an emitter and a cosine distributed direction leaving it.
+    pub fn sample_emission(&self, sampler: &mut dyn Sampler) -> Option<EmissionSample<'a>> {
+        let (emitter, light_pdf) = self.sample(sampler.get_1d())?;
+        let (point, normal, pdf_point) = emitter.sample_point(sampler.get_2d());
+        if pdf_point == 0.0 {
+            return None;
+        }
+
+        // Two-sided emitters pick the side to leave from with one more sample.
+        let side = if emitter.element.material().is_two_sided() && sampler.get_1d() < 0.5 {
+            -normal
+        } else {
+            normal
+        };
+        let direction = to_world(&cosine_sample_hemisphere(sampler.get_2d()), &side);
+        let pdf_dir = emission_pdf(emitter.element, &normal, &direction);
+        if pdf_dir == 0.0 {
+            return None;
+        }
+
+        Some(EmissionSample {
+            element: emitter.element,
+            point,
+            normal,
+            direction,
+            radiance: emitter.element.emitted(&point),
+            pdf_pos: light_pdf * pdf_point,
+            pdf_dir,
+        })
+    }
+}
+
+/// Convex polygon where the plane crosses the box, ordered around its centroid.
+fn clip_plane(plane: &Plane, bounds: &(Point, Point)) -> Vec<Point> {
+    let (min, max) = *bounds;
+    let corners: Vec<Point> = (0..8)
+        .map(|i| {
+            Point::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        })
+        .collect();
+    let distance = |p: &Point| plane.normal.dot(&(*p - plane.origin));
+
+    let mut points: Vec<Point> = vec![];
+    let push = |p: Point, points: &mut Vec<Point>| {
+        if points.iter().all(|q| (p - *q).length() > BOUNDS_EPSILON) {
+            points.push(p);
+        }
+    };
+
+    for (i, a) in corners.iter().enumerate() {
+        let da = distance(a);
+        if da.abs() < BOUNDS_EPSILON {
+            push(*a, &mut points);
+        }
+
+        for bit in &[1, 2, 4] {
+            if i & bit != 0 {
+                continue;
+            }
+            let b = corners[i | bit];
+            let db = distance(&b);
+            if (da < -BOUNDS_EPSILON && db > BOUNDS_EPSILON)
+                || (da > BOUNDS_EPSILON && db < -BOUNDS_EPSILON)
+            {
+                push(*a + (b - *a) * (da / (da - db)), &mut points);
+            }
+        }
+    }
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    let count = points.len() as f64;
+    let centroid = points.iter().fold(Vector3::zero(), |acc, p| {
+        acc + Vector3::from_point(p) * (1.0 / count)
+    });
+    let (tangent, bitangent) = create_coordinate_system(&plane.normal.normalize());
+    let angle = |p: &Point| {
+        let d = Vector3::from_point(p) - centroid;
+        d.dot(&bitangent).atan2(d.dot(&tangent))
+    };
+    points.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+
+    points
+}
+
+// Elements are told apart by address, the scene outlives the emitters.
+fn element_key(element: &Element) -> usize {
+    element as *const Element as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pbrt::sampler::HashSampler;
+    use crate::pbrt::scene::{Attributes, Polygon};
+
+    fn emissive(two_sided: bool) -> Material {
+        Material::Emissive {
+            emission: Coloration::Color(Color::white()),
+            intensity: 4.0,
+            two_sided,
+            spectrum: None,
+        }
+    }
+
+    fn scene() -> Scene {
+        let triangle = |y: f64, two_sided: bool| {
+            Element::Polygon(Polygon {
+                vertices: [
+                    Vector3 {
+                        x: -1.0,
+                        y,
+                        z: -4.0,
+                    },
+                    Vector3 { x: 1.0, y, z: -4.0 },
+                    Vector3 { x: 0.0, y, z: -6.0 },
+                ],
+                normal: Vector3::down(),
+                texture_coords: [TextureCoords { x: 0.0, y: 0.0 }; 3],
+                material: emissive(two_sided),
+                attributes: Attributes::default(),
+            })
+        };
+        let sphere = |x: f64, radius: f64| {
+            Element::Sphere(Sphere {
+                center: Point::new(x, 0.0, -5.0),
+                radius,
+                material: emissive(false),
+                attributes: Attributes::default(),
+            })
+        };
+
+        Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            entities: vec![
+                triangle(3.0, false),
+                triangle(-3.0, true),
+                sphere(-3.0, 1.0),
+                sphere(3.0, 0.5),
+            ],
+            lights: vec![],
+        }
+    }
+
+    #[test]
+    fn incident_pdf_matches_sampling() {
+        let scene = scene();
+        let emitters = Emitters::new(&scene);
+        let receivers = [
+            (Point::new(0.0, 0.0, -5.0), Vector3::up()),
+            (Point::new(0.0, 0.0, 0.0), Vector3::backward()),
+            (Point::new(1.5, -1.0, -5.0), Vector3::left()),
+        ];
+
+        for (receiver, normal) in &receivers {
+            let mut sampler = HashSampler::new(&[receiver.x, receiver.z]);
+            let mut found = 0;
+            for _ in 0..1000 {
+                let sample = match emitters.sample_incident(receiver, normal, &mut sampler) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                found += 1;
+
+                let pdf = emitters.pdf_incident(receiver, normal, sample.element, &sample.point);
+                assert!(
+                    (pdf / sample.pdf - 1.0).abs() < 1e-6,
+                    "{} != {}",
+                    pdf,
+                    sample.pdf
+                );
+            }
+            assert!(found > 500);
+        }
+    }
+
+    #[test]
+    fn light_pmf_sums_to_one() {
+        let scene = scene();
+        let emitters = Emitters::new(&scene);
+        let receiver = Point::new(0.5, 0.5, -3.0);
+        let normal = Vector3::up();
+
+        let total: f64 = (0..scene.entities.len())
+            .map(|index| emitters.bvh.pmf(&receiver, &normal, index))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::pbrt::point::Point;
+use crate::pbrt::vector3::Vector3;
+
+/// Spatial and directional extent of the light sent out by one or more emitters.
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub min: Point,
+    pub max: Point,
+    /// Emitted power, only compared with other bounds.
+    pub power: f64,
+    /// Axis of the cone the surface normals lie in.
+    pub axis: Vector3,
+    /// Cosine of the half-angle of that cone.
+    pub cos_theta_o: f64,
+    /// Cosine of the angle beyond the normals light still leaves at.
+    pub cos_theta_e: f64,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    fn centroid(&self) -> Point {
+        Point::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0.0 {
+            return *other;
+        }
+        if other.power == 0.0 {
+            return *self;
+        }
+
+        let (axis, cos_theta_o) =
+            cone_union(self.axis, self.cos_theta_o, other.axis, other.cos_theta_o);
+
+        LightBounds {
+            min: Point::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Conservative estimate of the light reaching `point` on a surface with `normal`.
+    fn importance(&self, point: &Point, normal: &Vector3) -> f64 {
+        let centroid = self.centroid();
+        let diagonal = self.max - self.min;
+        let distance2 = (*point - centroid).norm().max(diagonal.length() * 0.5);
+
+        // Angle between the cone axis and the direction towards the point.
+        let to_point = (*point - centroid).normalize();
+        let mut cos_theta_w = self.axis.dot(&to_point);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = (1.0 - cos_theta_w * cos_theta_w).max(0.0).sqrt();
+
+        // Angle the bounds subtend as seen from the point.
+        let radius2 = diagonal.norm() * 0.25;
+        let cos_theta_b = if (*point - centroid).norm() < radius2 {
+            -1.0
+        } else {
+            (1.0 - radius2 / (*point - centroid).norm()).max(0.0).sqrt()
+        };
+        let sin_theta_b = (1.0 - cos_theta_b * cos_theta_b).max(0.0).sqrt();
+
+        // Smallest angle to the cone of normals any emitter in the bounds can have.
+        let sin_theta_o = (1.0 - self.cos_theta_o * self.cos_theta_o).max(0.0).sqrt();
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = (1.0 - cos_theta_x * cos_theta_x).max(0.0).sqrt();
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.power * cos_theta_p / distance2;
+
+        // Same for the incident angle at the receiving surface.
+        let cos_theta_i = (-to_point).dot(normal).abs();
+        let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+        importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+
+        importance.max(0.0)
+    }
+}
+
+// Cosine of `a - b`, one when `b` is the larger angle.
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    cos_a * cos_b + sin_a * sin_b
+}
+
+// Smallest cone containing both cones given by their axis and the cosine of their half-angle.
+fn cone_union(a: Vector3, cos_a: f64, b: Vector3, cos_b: f64) -> (Vector3, f64) {
+    let pi = std::f64::consts::PI;
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = a.dot(&b).clamp(-1.0, 1.0).acos();
+
+    if (theta_d + theta_b).min(pi) <= theta_a {
+        return (a, cos_a);
+    }
+    if (theta_d + theta_a).min(pi) <= theta_b {
+        return (b, cos_b);
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) * 0.5;
+    if theta_o >= pi {
+        return (a, -1.0);
+    }
+
+    // Rotate `a` towards `b` so the new cone just touches both.
+    let theta_r = theta_o - theta_a;
+    let rotation_axis = a.cross(&b);
+    if rotation_axis.norm() == 0.0 {
+        return (a, -1.0);
+    }
+    let k = rotation_axis.normalize();
+    let axis =
+        a * theta_r.cos() + k.cross(&a) * theta_r.sin() + k * (k.dot(&a) * (1.0 - theta_r.cos()));
+
+    (axis.normalize(), theta_o.cos())
+}
+
+enum Node {
+    Leaf { light: usize },
+    // The first child directly follows its parent.
+    Interior { second_child: usize },
+}
+
+/// Bounding volume hierarchy over the emitters, picking one with probability proportional to
+/// the importance of the nodes on the way down.
+pub struct LightBvh {
+    nodes: Vec<(LightBounds, Node)>,
+    // Way down to the leaf of every light, one bit per level set when the second child is
+    // taken, `None` for lights without power.
+    trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[LightBounds]) -> LightBvh {
+        let mut bvh = LightBvh {
+            nodes: vec![],
+            trails: vec![None; lights.len()],
+        };
+        let mut indices: Vec<usize> = (0..lights.len())
+            .filter(|&i| lights[i].power > 0.0)
+            .collect();
+        if !indices.is_empty() {
+            bvh.build(lights, &mut indices, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(
+        &mut self,
+        lights: &[LightBounds],
+        indices: &mut [usize],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        let node = self.nodes.len();
+
+        if indices.len() == 1 {
+            let bounds = lights[indices[0]];
+            self.nodes.push((bounds, Node::Leaf { light: indices[0] }));
+            self.trails[indices[0]] = Some(trail);
+            return bounds;
+        }
+
+        // Split at the median along the widest extent of the centroids.
+        let centroids: Vec<Point> = indices.iter().map(|&i| lights[i].centroid()).collect();
+        let extent = |f: fn(&Point) -> f64| {
+            let values = centroids.iter().map(f);
+            values.clone().fold(f64::NEG_INFINITY, f64::max) - values.fold(f64::INFINITY, f64::min)
+        };
+        let extents = [extent(|p| p.x), extent(|p| p.y), extent(|p| p.z)];
+        let axis = (0..3)
+            .max_by(|&a, &b| extents[a].total_cmp(&extents[b]))
+            .unwrap();
+        let coordinate = |i: &usize| {
+            let c = lights[*i].centroid();
+            [c.x, c.y, c.z][axis]
+        };
+        indices.sort_by(|a, b| coordinate(a).total_cmp(&coordinate(b)));
+
+        self.nodes
+            .push((lights[indices[0]], Node::Interior { second_child: 0 }));
+        let middle = indices.len() / 2;
+        let (first, second) = indices.split_at_mut(middle);
+
+        let first_bounds = self.build(lights, first, trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second_bounds = self.build(lights, second, trail | (1 << depth), depth + 1);
+
+        let bounds = first_bounds.union(&second_bounds);
+        self.nodes[node] = (bounds, Node::Interior { second_child });
+        bounds
+    }
+
+    /// Index of a light picked for `point` on a surface with `normal`, and its probability.
+    pub fn sample(&self, point: &Point, normal: &Vector3, mut u: f64) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut node = 0;
+        let mut pmf = 1.0;
+        loop {
+            match self.nodes[node].1 {
+                Node::Leaf { light } => {
+                    if node > 0 || self.nodes[node].0.importance(point, normal) > 0.0 {
+                        return Some((light, pmf));
+                    }
+                    return None;
+                }
+                Node::Interior { second_child } => {
+                    let first = self.nodes[node + 1].0.importance(point, normal);
+                    let second = self.nodes[second_child].0.importance(point, normal);
+                    if first == 0.0 && second == 0.0 {
+                        return None;
+                    }
+
+                    // Pick a child and stretch what's left of `u` back over [0, 1).
+                    let p_first = first / (first + second);
+                    if u < p_first {
+                        u = (u / p_first).min(1.0 - f64::EPSILON);
+                        pmf *= p_first;
+                        node += 1;
+                    } else {
+                        u = ((u - p_first) / (1.0 - p_first)).min(1.0 - f64::EPSILON);
+                        pmf *= 1.0 - p_first;
+                        node = second_child;
+                    }
+                }
+            }
+        }
+    }
+    /// Probability of `sample` picking `light` for `point` on a surface with `normal`.
+    pub fn pmf(&self, point: &Point, normal: &Vector3, light: usize) -> f64 {
+        let mut trail = match self.trails.get(light) {
+            Some(&Some(trail)) => trail,
+            _ => return 0.0,
+        };
+
+        let mut node = 0;
+        let mut pmf = 1.0;
+        loop {
+            match self.nodes[node].1 {
+                Node::Leaf { .. } => {
+                    if node > 0 || self.nodes[node].0.importance(point, normal) > 0.0 {
+                        return pmf;
+                    }
+                    return 0.0;
+                }
+                Node::Interior { second_child } => {
+                    let first = self.nodes[node + 1].0.importance(point, normal);
+                    let second = self.nodes[second_child].0.importance(point, normal);
+                    if first == 0.0 && second == 0.0 {
+                        return 0.0;
+                    }
+
+                    let p_first = first / (first + second);
+                    if trail & 1 == 0 {
+                        pmf *= p_first;
+                        node += 1;
+                    } else {
+                        pmf *= 1.0 - p_first;
+                        node = second_child;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::bdpt::{connect_paths, generate_camera_subpath, generate_light_subpath, Context};
+use super::{Integrator, EXPOSURE};
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::sampler::Sampler;
+use crate::pbrt::sampling::Distribution1D;
+use crate::pbrt::scene::Scene;
+
+const CAMERA_STREAM: usize = 0;
+const LIGHT_STREAM: usize = 1;
+const CONNECTION_STREAM: usize = 2;
+const STREAM_COUNT: usize = 3;
+
+/// Primary sample space Metropolis light transport over the bidirectional path tracer. Each
+/// Markov chain explores paths of a single depth, picked during the bootstrap phase.
+pub struct MltIntegrator {
+    pub max_depth: usize,
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub mutations_per_pixel: usize,
+    pub sigma: f64,
+    pub large_step_probability: f64,
+}
+
+impl Integrator for MltIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let context = Context::new(scene);
+        let mut film = Film::new(scene.width, scene.height, EXPOSURE);
+
+        // Bootstrap: estimate the image brightness and seed the chains proportionally to it.
+        let depths = self.max_depth + 1;
+        let bootstrap_weights: Vec<f64> = (0..self.bootstrap_samples * depths)
+            .map(|index| {
+                let mut sampler = self.create_sampler(index as u64);
+                let (radiance, _) = radiance(&context, &mut sampler, index % depths);
+                radiance.luminance() as f64
+            })
+            .collect();
+        let bootstrap = Distribution1D::new(&bootstrap_weights);
+        let b = bootstrap.integral * depths as f64;
+        if b == 0.0 {
+            return film;
+        }
+
+        let total_mutations = self.mutations_per_pixel * (scene.width * scene.height) as usize;
+        let mut rng = StdRng::seed_from_u64(bootstrap_weights.len() as u64);
+
+        for chain in 0..self.chains {
+            let chain_mutations = ((chain + 1) * total_mutations / self.chains)
+                .min(total_mutations)
+                - chain * total_mutations / self.chains;
+
+            let (index, _) = bootstrap.sample_discrete(rng.gen());
+            let depth = index % depths;
+
+            // Replaying the bootstrap seed recreates the chosen path as the initial state.
+            let mut sampler = self.create_sampler(index as u64);
+            let (mut current, mut current_raster) = radiance(&context, &mut sampler, depth);
+
+            for _ in 0..chain_mutations {
+                sampler.start_iteration();
+                let (proposed, proposed_raster) = radiance(&context, &mut sampler, depth);
+
+                let current_y = current.luminance() as f64;
+                let proposed_y = proposed.luminance() as f64;
+                let accept = if current_y > 0.0 {
+                    (proposed_y / current_y).min(1.0)
+                } else {
+                    1.0
+                };
+
+                // Splat both states, weighted by their expected share of the next one.
+                if accept > 0.0 {
+                    if let Some((x, y)) = proposed_raster {
+                        film.add_splat(x, y, proposed * (accept / proposed_y) as f32);
+                    }
+                }
+                if current_y > 0.0 {
+                    if let Some((x, y)) = current_raster {
+                        film.add_splat(x, y, current * ((1.0 - accept) / current_y) as f32);
+                    }
+                }
+
+                if rng.gen::<f64>() < accept {
+                    current = proposed;
+                    current_raster = proposed_raster;
+                    sampler.accept();
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+
+        film.set_splat_scale((self.mutations_per_pixel as f64 / b) as f32);
+        film
+    }
+}
+
+impl MltIntegrator {
+    fn create_sampler(&self, seed: u64) -> MltSampler {
+        MltSampler::new(seed, self.sigma, self.large_step_probability)
+    }
+}
+
+/// Radiance of a path with `depth` bounces whose strategy, pixel and vertices all come from
+/// the primary samples, scaled by the number of strategies it was chosen among.
+fn radiance(
+    context: &Context,
+    sampler: &mut dyn Sampler,
+    depth: usize,
+) -> (Color, Option<(u32, u32)>) {
+    sampler.start_stream(CAMERA_STREAM);
+    let (s, t, strategies) = if depth == 0 {
+        (0, 2, 1)
+    } else {
+        let strategies = depth + 2;
+        let s = ((sampler.get_1d() * strategies as f64) as usize).min(strategies - 1);
+        (s, strategies - s, strategies)
+    };
+
+    let camera = &context.camera;
+    let u = sampler.get_2d();
+    let raster_x = (u.0 * camera.width as f64).min(camera.width as f64 - 1.0);
+    let raster_y = (u.1 * camera.height as f64).min(camera.height as f64 - 1.0);
+    let (x, y) = (raster_x as u32, raster_y as u32);
+    let ray = camera.generate_ray(x, y, (raster_x.fract(), raster_y.fract()));
+
+    let mut camera_path = Vec::with_capacity(t);
+    generate_camera_subpath(context, ray, sampler, t, &mut camera_path);
+    if camera_path.len() != t {
+        return (Color::black(), None);
+    }
+
+    sampler.start_stream(LIGHT_STREAM);
+    let mut light_path = Vec::with_capacity(s);
+    // A single light vertex is sampled from the camera subpath when connecting.
+    if s > 1 {
+        generate_light_subpath(context, sampler, s, &mut light_path);
+        if light_path.len() != s {
+            return (Color::black(), None);
+        }
+    }
+
+    sampler.start_stream(CONNECTION_STREAM);
+    let (radiance, splat) = connect_paths(context, &light_path, &camera_path, s, t, sampler);
+    let raster = if t == 1 { splat } else { Some((x, y)) };
+
+    (radiance * strategies as f32, raster)
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    value_backup: f64,
+    last_modification: u64,
+    modify_backup: u64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.value_backup = self.value;
+        self.modify_backup = self.last_modification;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.value_backup;
+        self.last_modification = self.modify_backup;
+    }
+}
+
+/// Sampler whose values are a point in primary sample space, mutated lazily either by small
+/// gaussian perturbations or by large steps that draw fresh uniform values.
+struct MltSampler {
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+    stream_index: usize,
+    sample_index: usize,
+}
+
+impl MltSampler {
+    fn new(seed: u64, sigma: f64, large_step_probability: f64) -> MltSampler {
+        MltSampler {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            samples: vec![],
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            stream_index: 0,
+            sample_index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification == self.current_iteration {
+                sample.restore();
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let rng = &mut self.rng;
+        let sample = &mut self.samples[index];
+
+        // Catch up with a large step this sample missed while unused.
+        if sample.last_modification < self.last_large_step_iteration {
+            sample.value = rng.gen();
+            sample.last_modification = self.last_large_step_iteration;
+        }
+
+        sample.backup();
+        if self.large_step {
+            sample.value = rng.gen();
+        } else {
+            let small_steps = self.current_iteration - sample.last_modification;
+            let sigma = self.sigma * (small_steps as f64).sqrt();
+            sample.value += sample_normal(rng) * sigma;
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modification = self.current_iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn start_stream(&mut self, index: usize) {
+        self.stream_index = index;
+        self.sample_index = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let index = self.stream_index + STREAM_COUNT * self.sample_index;
+        self.sample_index += 1;
+        self.ensure_ready(index);
+        self.samples[index].value
+    }
+}
+
+fn sample_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.gen();
+    let u2: f64 = rng.gen();
+    (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
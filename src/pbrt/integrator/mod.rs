@@ -0,0 +1,334 @@
+use self::emitters::Emitters;
+use super::bsdf::{BsdfSample, SurfaceBsdf};
+use super::color::Color;
+use super::film::Film;
+use super::point::Point;
+use super::rendering::Ray;
+use super::sampler::Sampler;
+use super::scene::{Element, RayKind, Scene};
+use super::vector3::Vector3;
+
+pub mod bdpt;
+pub mod debug;
+pub mod emitters;
+pub mod light_bvh;
+pub mod mlt;
+pub mod path;
+pub mod sppm;
+pub mod subsurface;
+pub mod whitted;
+
+pub const FLOATING_POINT_BACKOFF: f64 = 0.01;
+pub const RAY_COUNT: u32 = 16;
+pub const BOUNCE_CAP: u32 = 8;
+// RAY_COUNT + BOUNCE_CAP
+pub const ROUND_COUNT: u32 = 128;
+
+pub const EXPOSURE: f32 = 1.0 / ROUND_COUNT as f32;
+
+pub trait Integrator {
+    fn render(&self, scene: &Scene) -> Film;
+}
+
+/// Kind of the ray continuing a path along a sampled direction, leaving `normal` with `wo` on
+/// the outgoing side.
+pub fn scattered_ray_kind(sample: &BsdfSample, normal: &Vector3, wo: &Vector3) -> RayKind {
+    if !sample.specular {
+        RayKind::Indirect
+    } else if normal.dot(wo) * normal.dot(&sample.wi) > 0.0 {
+        RayKind::Reflection
+    } else {
+        RayKind::Refraction
+    }
+}
+
+pub fn face_forward(normal: &Vector3, w: &Vector3) -> Vector3 {
+    if normal.dot(w) < 0.0 {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
+/// Ray leaving `point` along `direction`, pushed off the surface to the side it is heading.
+pub fn spawn_ray(point: &Point, normal: &Vector3, direction: Vector3) -> Ray {
+    let offset = face_forward(normal, &direction) * FLOATING_POINT_BACKOFF;
+
+    Ray {
+        origin: *point + offset,
+        direction,
+    }
+}
+
+pub fn unoccluded(scene: &Scene, from: &Point, normal: &Vector3, to: &Point) -> bool {
+    let delta = *to - *from;
+    let distance = delta.length();
+
+    unoccluded_along(scene, from, normal, delta * (1.0 / distance), distance)
+}
+
+/// Whether nothing casting shadows blocks the segment leaving `from` along the unit `direction`, which may be
+/// infinitely long.
+pub fn unoccluded_along(
+    scene: &Scene,
+    from: &Point,
+    normal: &Vector3,
+    direction: Vector3,
+    distance: f64,
+) -> bool {
+    let ray = spawn_ray(from, normal, direction);
+
+    match scene.trace_for(&ray, RayKind::Shadow) {
+        Some(intersection) => intersection.distance >= distance - 2.0 * FLOATING_POINT_BACKOFF,
+        None => true,
+    }
+}
+
+/// One sample of the light arriving straight from an emissive element linked to `element`,
+/// scattered towards `wo` by `bsdf`.
+pub fn sample_area_light(
+    scene: &Scene,
+    emitters: &Emitters,
+    element: &Element,
+    point: &Point,
+    bsdf: &SurfaceBsdf,
+    wo: &Vector3,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let normal = &bsdf.frame.normal;
+    let sample = match emitters.sample_incident(point, normal, sampler) {
+        Some(sample) => sample,
+        None => return Color::black(),
+    };
+    if !element
+        .attributes()
+        .is_lit_by(sample.element.attributes().light_group)
+    {
+        return Color::black();
+    }
+
+    let wi = (sample.point - *point).normalize();
+    let f = bsdf.f(wo, &wi);
+    if f.is_black() || !unoccluded(scene, point, normal, &sample.point) {
+        return Color::black();
+    }
+
+    let radiance = bsdf.incident(
+        &sample.radiance,
+        sample.element.material().emission_spectrum(),
+    );
+    f * radiance * (wi.dot(normal).abs() / sample.pdf) as f32
+}
+
+/// Fraction of the light surviving `distance` through a medium with the given absorption
+/// coefficient, by the Beer-Lambert law.
+pub fn beer_lambert(absorption: &Color, distance: f64) -> Color {
+    let survive = |coefficient: f32| (-coefficient as f64 * distance).exp() as f32;
+
+    Color {
+        r: survive(absorption.r),
+        g: survive(absorption.g),
+        b: survive(absorption.b),
+    }
+}
+
+/// Absorption of the interior a path is travelling through after scattering from `wo` to `wi` at
+/// the surface of `element`, whose `normal` points outwards. Transmission inwards enters the
+/// element, transmission outwards leaves it and reflection keeps the current `medium`.
+pub fn medium_after(
+    element: &Element,
+    normal: &Vector3,
+    wo: &Vector3,
+    wi: &Vector3,
+    medium: Option<Color>,
+) -> Option<Color> {
+    if normal.dot(wo) * normal.dot(wi) >= 0.0 {
+        medium
+    } else if normal.dot(wi) < 0.0 {
+        element.material().absorption()
+    } else {
+        None
+    }
+}
+
+/// Radiance of the lights at infinity seen along a ray that left `from`, or the camera, in
+/// `direction`. Lights not linked to `from` aren't seen.
+pub fn environment(scene: &Scene, from: Option<&Element>, direction: &Vector3) -> Color {
+    scene
+        .lights
+        .iter()
+        .filter(|light| is_linked(from, light.light_group()))
+        .fold(Color::black(), |acc, light| acc + light.le(direction))
+}
+
+/// Whether light of `group` may arrive straight at `receiver`. The camera, `None`, sees every
+/// light.
+pub fn is_linked(receiver: Option<&Element>, group: u32) -> bool {
+    receiver.is_none_or(|element| element.attributes().is_lit_by(group))
+}
+
+/// Light reaching `point` from every light of the scene linked to `element` through a shadow
+/// ray, scattered towards `wo` by `bsdf`.
+pub fn sample_lights(
+    scene: &Scene,
+    element: &Element,
+    point: &Point,
+    bsdf: &SurfaceBsdf,
+    wo: &Vector3,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let normal = &bsdf.frame.normal;
+    scene.lights.iter().fold(Color::black(), |acc, light| {
+        if !element.attributes().is_lit_by(light.light_group()) {
+            return acc;
+        }
+
+        let sample = match light.sample_incident(point, sampler) {
+            Some(sample) if sample.pdf > 0.0 && !sample.radiance.is_black() => sample,
+            _ => return acc,
+        };
+
+        let wi = sample.direction;
+        let f = bsdf.f(wo, &wi);
+        if f.is_black() || !unoccluded_along(scene, point, normal, wi, sample.distance) {
+            return acc;
+        }
+
+        acc + f * bsdf.incident(&sample.radiance, None) * (wi.dot(normal).abs() / sample.pdf) as f32
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bdpt::BdptIntegrator;
+    use super::mlt::MltIntegrator;
+    use super::path::PathIntegrator;
+    use super::sppm::SppmIntegrator;
+    use super::*;
+    use crate::pbrt::light::{DirectionalLight, PointLight, DEFAULT_LIGHT_GROUP};
+    use crate::pbrt::scene::{Attributes, Coloration, Material, Plane, Sphere};
+
+    fn floor(light_links: u32) -> Element {
+        Element::Plane(Plane {
+            origin: Point::new(0.0, -1.0, 0.0),
+            normal: Vector3::down(),
+            material: Material::Diffuse {
+                albedo: 0.8,
+                color: Coloration::Color(Color::white()),
+                sigma: 0.0,
+            },
+            attributes: Attributes {
+                light_links,
+                ..Attributes::default()
+            },
+        })
+    }
+
+    // Diffuse floor under the camera, lit by an emissive sphere behind it that it may be
+    // linked to.
+    fn scene(floor_links: u32) -> Scene {
+        Scene {
+            width: 4,
+            height: 4,
+            fov: 90.0,
+            entities: vec![
+                floor(floor_links),
+                Element::Sphere(Sphere {
+                    center: Point::new(0.0, 2.0, 3.0),
+                    radius: 1.0,
+                    material: Material::Emissive {
+                        emission: Coloration::Color(Color::white()),
+                        intensity: 50.0,
+                        two_sided: false,
+                        spectrum: None,
+                    },
+                    attributes: Attributes {
+                        light_group: 2,
+                        ..Attributes::default()
+                    },
+                }),
+            ],
+            lights: vec![],
+        }
+    }
+
+    fn brightness(film: &Film, scene: &Scene) -> f32 {
+        let mut total = 0.0;
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                let color = film.pixel(x, y);
+                total += color.r + color.g + color.b;
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn unlinked_light_leaves_element_black() {
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(PathIntegrator { spectral: false }),
+            Box::new(BdptIntegrator::new(4)),
+            Box::new(SppmIntegrator::new(2, 2000, 0.5, 4)),
+        ];
+
+        for integrator in &integrators {
+            let linked = scene(2);
+            assert!(brightness(&integrator.render(&linked), &linked) > 0.0);
+
+            let unlinked = scene(1);
+            assert_eq!(brightness(&integrator.render(&unlinked), &unlinked), 0.0);
+        }
+    }
+
+    #[test]
+    fn light_sources_light_every_integrator() {
+        let scene = Scene {
+            width: 4,
+            height: 4,
+            fov: 90.0,
+            entities: vec![floor(!0)],
+            lights: vec![
+                Box::new(PointLight {
+                    position: Point::new(0.0, 3.0, -2.0),
+                    color: Color::white(),
+                    intensity: 5.0,
+                    profile: None,
+                    light_group: DEFAULT_LIGHT_GROUP,
+                }),
+                Box::new(DirectionalLight {
+                    direction: Vector3::down(),
+                    color: Color::white(),
+                    intensity: 1.0,
+                    light_group: DEFAULT_LIGHT_GROUP,
+                }),
+            ],
+        };
+
+        // Only direct light reaches the lone floor, so all agree up to noise and the pixel
+        // centers the path tracer shoots through.
+        let expected = brightness(&PathIntegrator { spectral: false }.render(&scene), &scene);
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(BdptIntegrator::new(4)),
+            Box::new(SppmIntegrator::new(2, 2000, 0.5, 4)),
+            Box::new(MltIntegrator {
+                max_depth: 4,
+                bootstrap_samples: 1000,
+                chains: 10,
+                mutations_per_pixel: 1000,
+                sigma: 0.01,
+                large_step_probability: 0.3,
+            }),
+        ];
+
+        assert!(expected > 0.0);
+        for integrator in &integrators {
+            let total = brightness(&integrator.render(&scene), &scene);
+            assert!(
+                (total / expected - 1.0).abs() < 0.1,
+                "{} != {}",
+                total,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use super::emitters::Emitters;
+use super::subsurface::Interior;
+use super::{
+    beer_lambert, environment, is_linked, medium_after, sample_area_light, sample_lights,
+    scattered_ray_kind, spawn_ray, Integrator, BOUNCE_CAP, EXPOSURE, RAY_COUNT,
+};
+use crate::pbrt::bsdf::refracted_radiance_scale;
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::point::Point;
+use crate::pbrt::rendering::{Intersectable, Ray};
+use crate::pbrt::sampler::{RandomSampler, Sampler};
+use crate::pbrt::scene::{Element, Material, RayKind, Scene};
+use crate::pbrt::spectrum::{
+    sample_visible_wavelength, wavelength_color, SampledWavelengths, Spectrum,
+};
+use crate::pbrt::vector3::Vector3;
+
+/// Unidirectional path tracer with next event estimation. In `spectral` mode paths carry three
+/// sampled wavelengths instead of RGB, with colors upsampled to spectra, and the film
+/// accumulates CIE XYZ.
+pub struct PathIntegrator {
+    pub spectral: bool,
+}
+
+impl Integrator for PathIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let emitters = Emitters::new(scene);
+        let mut sampler = RandomSampler::default();
+        let mut film = if self.spectral {
+            Film::new_xyz(scene.width, scene.height, EXPOSURE)
+        } else {
+            Film::new(scene.width, scene.height, EXPOSURE)
+        };
+
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                for _ in 0..RAY_COUNT {
+                    if self.spectral {
+                        let mut wavelengths = Some(SampledWavelengths::sample(sampler.get_1d()));
+                        let radiance =
+                            get_color(scene, &emitters, x, y, &mut wavelengths, &mut sampler);
+                        if let Some(ref wavelengths) = wavelengths {
+                            film.add_sample(x, y, wavelengths.xyz(&radiance));
+                        }
+                    } else {
+                        let color = get_color(scene, &emitters, x, y, &mut None, &mut sampler);
+                        film.add_sample(x, y, color);
+                    }
+                }
+            }
+        }
+
+        film
+    }
+}
+
+/// Radiance arriving through a path from pixel `x`, `y`. With `wavelengths` it is spectral, and
+/// the wavelengths may be cut down to the first one by dispersion.
+fn get_color(
+    scene: &Scene,
+    emitters: &Emitters,
+    x: u32,
+    y: u32,
+    wavelengths: &mut Option<SampledWavelengths>,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let mut color = Color::black();
+    let mut beta = Color::white();
+    let mut ray = Ray::create_prime(x, y, scene);
+    let mut kind = RayKind::Camera;
+    // Point and normal the lights were already sampled directly from, when the ray was
+    // scattered there by a non-specular lobe.
+    let mut lights_sampled: Option<(Point, Vector3)> = None;
+    // Element the ray leaves, `None` for the camera.
+    let mut from: Option<&Element> = None;
+    // Absorption of the dielectric the path is inside of.
+    let mut medium = None;
+    // Single wavelength followed since the path met a dispersive surface, until then the path
+    // carries the whole spectrum.
+    let mut wavelength = None;
+
+    for _ in 0..BOUNCE_CAP {
+        let intersection = match scene.trace_for(&ray, kind) {
+            Some(intersection) => intersection,
+            None => {
+                if lights_sampled.is_none() {
+                    let radiance =
+                        arriving(environment(scene, from, &ray.direction), None, wavelengths);
+                    color = color + radiance * beta;
+                }
+                break;
+            }
+        };
+
+        if let Some(ref absorption) = medium {
+            beta = beta * beer_lambert(absorption, intersection.distance);
+        }
+
+        let element = intersection.element;
+        let hit_point = ray.origin + (ray.direction * intersection.distance);
+        let normal = element.surface_normal(&hit_point);
+        let wo = -ray.direction;
+
+        let sampled = lights_sampled.is_some_and(|(receiver, receiver_normal)| {
+            emitters.pdf_incident(&receiver, &receiver_normal, element, &hit_point) > 0.0
+        });
+        if element.material().is_emissive()
+            && !sampled
+            && is_linked(from, element.attributes().light_group)
+        {
+            let radiance = arriving(
+                element.emitted_towards(&hit_point, &normal, &wo),
+                element.material().emission_spectrum(),
+                wavelengths,
+            );
+            color = color + radiance * beta;
+        }
+
+        if wavelength.is_none() && element.material().is_dispersive() {
+            match *wavelengths {
+                Some(ref mut wavelengths) => {
+                    wavelengths.terminate_secondary();
+                    wavelength = Some(wavelengths.lambda[0]);
+                }
+                None => {
+                    let lambda = sample_visible_wavelength(sampler.get_1d());
+                    beta = beta * wavelength_color(lambda);
+                    wavelength = Some(lambda);
+                }
+            }
+        }
+
+        let mut bsdf = match element.bsdf_at_wavelength(&hit_point, wavelength) {
+            Some(bsdf) => bsdf,
+            None => break,
+        };
+        bsdf.wavelengths = *wavelengths;
+        if !bsdf.is_specular() {
+            let direct = sample_lights(scene, element, &hit_point, &bsdf, &wo, sampler)
+                + sample_area_light(scene, emitters, element, &hit_point, &bsdf, &wo, sampler);
+            color = color + direct * beta;
+        }
+
+        let sample = match bsdf.sample_f(&wo, sampler) {
+            Some(sample) => sample,
+            None => break,
+        };
+
+        // Light a delta lobe reflects isn't seen by direct lighting, even on a surface that
+        // sampled it, so emitters hit next still count.
+        lights_sampled = if sample.specular {
+            None
+        } else {
+            Some((hit_point, normal))
+        };
+        beta = beta * sample.f * (sample.wi.dot(&normal).abs() / sample.pdf) as f32;
+        if beta.is_black() {
+            break;
+        }
+
+        // Light refracted into a translucent element walks through it and continues from where
+        // it leaves.
+        if normal.dot(&wo) > 0.0 && normal.dot(&sample.wi) < 0.0 {
+            if let Some(interior) = interior(element.material(), wavelengths) {
+                let entry = spawn_ray(&hit_point, &normal, sample.wi);
+                let (exit, weight) = match interior.random_walk(scene, entry, sampler) {
+                    Some(exit) => exit,
+                    None => break,
+                };
+                // Leaving the denser interior undoes the compression of the radiance entering it.
+                beta = beta * weight * refracted_radiance_scale(-1.0, interior.index as f64);
+                kind = RayKind::Refraction;
+                from = Some(element);
+                ray = exit;
+                continue;
+            }
+        }
+
+        medium = medium_after(element, &normal, &wo, &sample.wi, medium);
+        kind = scattered_ray_kind(&sample, &normal, &wo);
+        ray = spawn_ray(&hit_point, &normal, sample.wi);
+        from = Some(element);
+    }
+
+    color
+}
+
+// Scattering interior of `material` in the terms of the path, `None` for materials without one.
+fn interior(material: &Material, wavelengths: &Option<SampledWavelengths>) -> Option<Interior> {
+    match *material {
+        Material::Subsurface {
+            index,
+            albedo,
+            mean_free_path,
+        } => Some(match *wavelengths {
+            Some(ref wavelengths) => Interior::new(
+                index,
+                wavelengths.reflectance(&albedo),
+                wavelengths.reflectance(&mean_free_path),
+            ),
+            None => Interior::new(index, albedo, mean_free_path),
+        }),
+        _ => None,
+    }
+}
+
+// Light with the RGB `radiance` reaching a path, upsampled to its wavelengths in spectral mode.
+fn arriving(
+    radiance: Color,
+    spectrum: Option<&Spectrum>,
+    wavelengths: &Option<SampledWavelengths>,
+) -> Color {
+    match *wavelengths {
+        Some(ref wavelengths) => wavelengths.emission(&radiance, spectrum),
+        None => radiance,
+    }
+}
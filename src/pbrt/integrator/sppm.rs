@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use super::emitters::Emitters;
+use super::{
+    beer_lambert, environment, is_linked, medium_after, sample_area_light, sample_lights,
+    scattered_ray_kind, spawn_ray, Integrator, EXPOSURE,
+};
+use crate::pbrt::bsdf::{SurfaceBsdf, TransportMode};
+use crate::pbrt::camera::Camera;
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::point::Point;
+use crate::pbrt::rendering::{Intersectable, Ray};
+use crate::pbrt::sampler::{RandomSampler, Sampler};
+use crate::pbrt::scene::{RayKind, Scene};
+use crate::pbrt::vector3::Vector3;
+
+// Fraction of the new photons kept at each radius reduction.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// Stochastic progressive photon mapping. Every iteration finds one visible point per pixel,
+/// shoots photons from the emitters and shrinks the gather radius of the pixels that got some.
+pub struct SppmIntegrator {
+    pub iterations: u32,
+    pub photons_per_iteration: usize,
+    pub initial_radius: f64,
+    pub max_depth: usize,
+}
+
+impl SppmIntegrator {
+    pub fn new(
+        iterations: u32,
+        photons_per_iteration: usize,
+        initial_radius: f64,
+        max_depth: usize,
+    ) -> SppmIntegrator {
+        SppmIntegrator {
+            iterations,
+            photons_per_iteration,
+            initial_radius,
+            max_depth,
+        }
+    }
+}
+
+struct VisiblePoint {
+    point: Point,
+    wo: Vector3,
+    beta: Color,
+    bsdf: SurfaceBsdf,
+}
+
+struct SppmPixel {
+    radius: f64,
+    ld: Color,
+    visible_point: Option<VisiblePoint>,
+    phi: Color,
+    m: u32,
+    n: f64,
+    tau: Color,
+}
+
+impl Integrator for SppmIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let camera = Camera::new(scene);
+        let emitters = Emitters::new(scene);
+        let mut sampler = RandomSampler::default();
+
+        let mut pixels: Vec<SppmPixel> = (0..scene.width * scene.height)
+            .map(|_| SppmPixel {
+                radius: self.initial_radius,
+                ld: Color::black(),
+                visible_point: None,
+                phi: Color::black(),
+                m: 0,
+                n: 0.0,
+                tau: Color::black(),
+            })
+            .collect();
+
+        for _ in 0..self.iterations {
+            for y in 0..scene.height {
+                for x in 0..scene.width {
+                    let pixel = &mut pixels[(y * scene.width + x) as usize];
+                    let ray = camera.generate_ray(x, y, sampler.get_2d());
+                    self.find_visible_point(scene, &emitters, ray, pixel, &mut sampler);
+                }
+            }
+
+            let grid = Grid::new(&pixels);
+            for _ in 0..self.photons_per_iteration {
+                self.trace_photon(scene, &emitters, &grid, &mut pixels, &mut sampler);
+            }
+
+            for pixel in pixels.iter_mut() {
+                if pixel.m > 0 {
+                    let n_new = pixel.n + ALPHA * pixel.m as f64;
+                    let radius_new = pixel.radius * (n_new / (pixel.n + pixel.m as f64)).sqrt();
+                    let phi = match pixel.visible_point {
+                        Some(ref vp) => vp.beta * pixel.phi,
+                        None => Color::black(),
+                    };
+                    let shrink = (radius_new * radius_new) / (pixel.radius * pixel.radius);
+
+                    pixel.tau = (pixel.tau + phi) * shrink as f32;
+                    pixel.n = n_new;
+                    pixel.radius = radius_new;
+                    pixel.m = 0;
+                    pixel.phi = Color::black();
+                }
+                pixel.visible_point = None;
+            }
+        }
+
+        let mut film = Film::new(scene.width, scene.height, EXPOSURE);
+        let photon_count = self.iterations as f64 * self.photons_per_iteration as f64;
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                let pixel = &pixels[(y * scene.width + x) as usize];
+                let direct = pixel.ld * (1.0 / self.iterations as f32);
+                let area = std::f64::consts::PI * pixel.radius * pixel.radius;
+                let indirect = pixel.tau * (1.0 / (photon_count * area)) as f32;
+
+                film.add_sample(x, y, direct + indirect);
+            }
+        }
+
+        film
+    }
+}
+
+impl SppmIntegrator {
+    /// Follows the camera ray through specular bounces up to the first diffuse surface, adding
+    /// the light reaching the camera directly along the way.
+    fn find_visible_point(
+        &self,
+        scene: &Scene,
+        emitters: &Emitters,
+        mut ray: Ray,
+        pixel: &mut SppmPixel,
+        sampler: &mut dyn Sampler,
+    ) {
+        let mut beta = Color::white();
+        let mut kind = RayKind::Camera;
+        let mut medium = None;
+        // Element the ray leaves, `None` for the camera.
+        let mut from = None;
+
+        for _ in 0..self.max_depth {
+            let intersection = match scene.trace_for(&ray, kind) {
+                Some(intersection) => intersection,
+                None => {
+                    pixel.ld = pixel.ld + beta * environment(scene, from, &ray.direction);
+                    return;
+                }
+            };
+            if let Some(ref absorption) = medium {
+                beta = beta * beer_lambert(absorption, intersection.distance);
+            }
+
+            let element = intersection.element;
+            let point = ray.origin + (ray.direction * intersection.distance);
+            let normal = element.surface_normal(&point);
+            let wo = -ray.direction;
+
+            // Only specular bounces got here, light sampling can't find these emitters.
+            if is_linked(from, element.attributes().light_group) {
+                pixel.ld = pixel.ld + beta * element.emitted_towards(&point, &normal, &wo);
+            }
+
+            let bsdf = match element.bsdf(&point) {
+                Some(bsdf) => bsdf,
+                None => return,
+            };
+
+            if !bsdf.is_specular() {
+                let direct = sample_lights(scene, element, &point, &bsdf, &wo, sampler)
+                    + sample_area_light(scene, emitters, element, &point, &bsdf, &wo, sampler);
+                pixel.ld = pixel.ld + beta * direct;
+                pixel.visible_point = Some(VisiblePoint {
+                    point,
+                    wo,
+                    beta,
+                    bsdf,
+                });
+                return;
+            }
+
+            let sample = match bsdf.sample_f(&wo, sampler) {
+                Some(sample) => sample,
+                None => return,
+            };
+            beta = beta * sample.f * (sample.wi.dot(&normal).abs() / sample.pdf) as f32;
+            medium = medium_after(element, &normal, &wo, &sample.wi, medium);
+            kind = scattered_ray_kind(&sample, &normal, &wo);
+            ray = spawn_ray(&point, &normal, sample.wi);
+            from = Some(element);
+        }
+    }
+
+    fn trace_photon(
+        &self,
+        scene: &Scene,
+        emitters: &Emitters,
+        grid: &Grid,
+        pixels: &mut [SppmPixel],
+        sampler: &mut dyn Sampler,
+    ) {
+        let (mut ray, mut beta, light_group) = match emit_photon(scene, emitters, sampler) {
+            Some(photon) => photon,
+            None => return,
+        };
+        let mut kind = RayKind::Indirect;
+        let mut medium = None;
+
+        for depth in 0..self.max_depth {
+            let intersection = match scene.trace_for(&ray, kind) {
+                Some(intersection) => intersection,
+                None => return,
+            };
+            if let Some(ref absorption) = medium {
+                beta = beta * beer_lambert(absorption, intersection.distance);
+            }
+
+            let element = intersection.element;
+            if depth == 0 && !is_linked(Some(element), light_group) {
+                return;
+            }
+            let point = ray.origin + (ray.direction * intersection.distance);
+            let normal = element.surface_normal(&point);
+            let wi = -ray.direction;
+
+            // Direct lighting is estimated at the visible points, photons only carry indirect.
+            if depth > 0 {
+                for &index in grid.candidates(&point) {
+                    let pixel = &mut pixels[index];
+                    if let Some(ref vp) = pixel.visible_point {
+                        if (vp.point - point).norm() > pixel.radius * pixel.radius {
+                            continue;
+                        }
+
+                        let f = vp.bsdf.f(&vp.wo, &wi);
+                        pixel.phi = pixel.phi + beta * f;
+                        pixel.m += 1;
+                    }
+                }
+            }
+
+            let mut bsdf = match element.bsdf(&point) {
+                Some(bsdf) => bsdf,
+                None => return,
+            };
+            bsdf.mode = TransportMode::Importance;
+            let sample = match bsdf.sample_f(&wi, sampler) {
+                Some(sample) => sample,
+                None => return,
+            };
+            let beta_new = beta * sample.f * (sample.wi.dot(&normal).abs() / sample.pdf) as f32;
+            if beta_new.is_black() {
+                return;
+            }
+
+            // Russian roulette keeping the photon power roughly constant.
+            let q = (1.0 - max_component(&beta_new) / max_component(&beta)).max(0.0);
+            if sampler.get_1d() < q.into() {
+                return;
+            }
+            beta = beta_new * (1.0 / (1.0 - q));
+            medium = medium_after(element, &normal, &wi, &sample.wi, medium);
+            kind = scattered_ray_kind(&sample, &normal, &wi);
+            ray = spawn_ray(&point, &normal, sample.wi);
+        }
+    }
+}
+
+/// Ray of a photon leaving one of the emitters or lights, picked uniformly with the emitters
+/// counting as one, its power and the light group it comes from.
+fn emit_photon(
+    scene: &Scene,
+    emitters: &Emitters,
+    sampler: &mut dyn Sampler,
+) -> Option<(Ray, Color, u32)> {
+    let sources = scene.lights.len() + if emitters.is_empty() { 0 } else { 1 };
+    if sources == 0 {
+        return None;
+    }
+    let index = ((sampler.get_1d() * sources as f64) as usize).min(sources - 1);
+
+    if let Some(light) = scene.lights.get(index) {
+        let (min, max) = *emitters.bounds();
+        let center = min + (max - min) * 0.5;
+        let emission = light.sample_emission(&center, (max - min).length() * 0.5, sampler)?;
+        let ray = Ray {
+            origin: emission.origin,
+            direction: emission.direction,
+        };
+        return Some((ray, emission.power * sources as f32, light.light_group()));
+    }
+
+    let emission = emitters.sample_emission(sampler)?;
+    let cos_theta = emission.normal.dot(&emission.direction).abs();
+    let beta = emission.radiance * (cos_theta / (emission.pdf_pos * emission.pdf_dir)) as f32;
+    Some((
+        spawn_ray(&emission.point, &emission.normal, emission.direction),
+        beta * sources as f32,
+        emission.element.attributes().light_group,
+    ))
+}
+
+fn max_component(color: &Color) -> f32 {
+    color.r.max(color.g).max(color.b)
+}
+
+/// Uniform hash grid over the visible points, each stored in every cell its radius overlaps.
+struct Grid {
+    cell_size: f64,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl Grid {
+    fn new(pixels: &[SppmPixel]) -> Grid {
+        let cell_size = pixels
+            .iter()
+            .filter(|p| p.visible_point.is_some())
+            .fold(0.0f64, |acc, p| acc.max(p.radius))
+            .max(1e-6);
+
+        let mut grid = Grid {
+            cell_size,
+            cells: HashMap::new(),
+        };
+
+        for (index, pixel) in pixels.iter().enumerate() {
+            if let Some(ref vp) = pixel.visible_point {
+                let radius = Vector3::from_one(pixel.radius);
+                let min = grid.cell(&(vp.point - radius));
+                let max = grid.cell(&(vp.point + radius));
+
+                for x in min.0..=max.0 {
+                    for y in min.1..=max.1 {
+                        for z in min.2..=max.2 {
+                            grid.cells.entry((x, y, z)).or_default().push(index);
+                        }
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn cell(&self, point: &Point) -> (i64, i64, i64) {
+        (
+            (point.x / self.cell_size).floor() as i64,
+            (point.y / self.cell_size).floor() as i64,
+            (point.z / self.cell_size).floor() as i64,
+        )
+    }
+
+    fn candidates(&self, point: &Point) -> &[usize] {
+        match self.cells.get(&self.cell(point)) {
+            Some(indices) => indices,
+            None => &[],
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::{spawn_ray, FLOATING_POINT_BACKOFF};
+use crate::pbrt::bsdf::fresnel_dielectric;
+use crate::pbrt::color::Color;
+use crate::pbrt::rendering::{make_reflection, Intersectable, Ray};
+use crate::pbrt::sampler::Sampler;
+use crate::pbrt::sampling::uniform_sample_sphere;
+use crate::pbrt::scene::{RayKind, Scene};
+
+// Scattering events after which the light is considered absorbed.
+const MAX_SCATTERINGS: usize = 256;
+
+/// Scattering interior of a translucent element, in the terms of the path walking through it:
+/// RGB, or the sampled wavelengths of a spectral path.
+pub struct Interior {
+    /// Index of refraction of the boundary.
+    pub index: f32,
+    /// Fraction of the light kept at each scattering event.
+    pub albedo: Color,
+    /// Density of scattering and absorbing particles, one over the mean free path.
+    pub extinction: Color,
+}
+
+impl Interior {
+    pub fn new(index: f32, albedo: Color, mean_free_path: Color) -> Interior {
+        let coefficient = |distance: f32| 1.0 / distance.max(1e-6);
+
+        Interior {
+            index,
+            albedo,
+            extinction: Color {
+                r: coefficient(mean_free_path.r),
+                g: coefficient(mean_free_path.g),
+                b: coefficient(mean_free_path.b),
+            },
+        }
+    }
+
+    /// Follows light refracted into the interior along `ray`, scattering isotropically, until
+    /// it refracts out through the boundary. Returns the ray leaving the surface with the
+    /// throughput of the walk, `None` when the light got absorbed or escaped through a surface
+    /// that isn't closed.
+    pub fn random_walk(
+        &self,
+        scene: &Scene,
+        mut ray: Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let mut beta = Color::white();
+
+        for _ in 0..MAX_SCATTERINGS {
+            let intersection = scene.trace_for(&ray, RayKind::Refraction)?;
+
+            // Distances are sampled for a channel picked at random, so the density is the
+            // average over the channels.
+            let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+            let sigma = component(&self.extinction, channel) as f64;
+            let distance = -(1.0 - sampler.get_1d()).ln() / sigma;
+
+            if distance < intersection.distance {
+                let transmittance = self.transmittance(distance);
+                let density = average(&(self.extinction * transmittance));
+                if density == 0.0 {
+                    return None;
+                }
+
+                beta = beta * self.albedo * self.extinction * transmittance * (1.0 / density);
+                ray = Ray {
+                    origin: ray.origin + (ray.direction * distance),
+                    direction: uniform_sample_sphere(sampler.get_2d()),
+                };
+            } else {
+                let transmittance = self.transmittance(intersection.distance);
+                let density = average(&transmittance);
+                if density == 0.0 {
+                    return None;
+                }
+                beta = beta * transmittance * (1.0 / density);
+
+                // Leave through the boundary, or reflect back inside by the Fresnel term.
+                let point = ray.origin + (ray.direction * intersection.distance);
+                let normal = intersection.element.surface_normal(&point);
+                let kr = fresnel_dielectric(-ray.direction.dot(&normal), self.index as f64);
+                if sampler.get_1d() >= kr {
+                    if let Some(transmission) = Ray::create_transmission(
+                        normal,
+                        ray.direction,
+                        point,
+                        FLOATING_POINT_BACKOFF,
+                        self.index,
+                    ) {
+                        return Some((transmission, beta));
+                    }
+                }
+                ray = spawn_ray(&point, &normal, make_reflection(ray.direction, normal));
+            }
+
+            // Russian roulette once most of the light has been absorbed.
+            let survival = beta.r.max(beta.g).max(beta.b);
+            if survival < 0.25 {
+                if sampler.get_1d() >= survival as f64 {
+                    return None;
+                }
+                beta = beta * (1.0 / survival);
+            }
+        }
+
+        None
+    }
+
+    // Fraction of the light going `distance` through the interior without meeting a particle.
+    fn transmittance(&self, distance: f64) -> Color {
+        let channel = |sigma: f32| (-(sigma as f64) * distance).exp() as f32;
+
+        Color {
+            r: channel(self.extinction.r),
+            g: channel(self.extinction.g),
+            b: channel(self.extinction.b),
+        }
+    }
+}
+
+fn component(color: &Color, channel: usize) -> f32 {
+    match channel {
+        0 => color.r,
+        1 => color.g,
+        _ => color.b,
+    }
+}
+
+fn average(color: &Color) -> f32 {
+    (color.r + color.g + color.b) / 3.0
+}
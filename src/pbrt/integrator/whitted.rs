@@ -0,0 +1,230 @@
+use super::{
+    beer_lambert, environment, is_linked, medium_after, sample_lights, scattered_ray_kind,
+    spawn_ray, Integrator, EXPOSURE, FLOATING_POINT_BACKOFF,
+};
+use crate::pbrt::bsdf::{fresnel_dielectric, refracted_radiance_scale, SurfaceBsdf};
+use crate::pbrt::color::Color;
+use crate::pbrt::film::Film;
+use crate::pbrt::point::Point;
+use crate::pbrt::rendering::{make_reflection, Intersectable, Ray};
+use crate::pbrt::sampler::{RandomSampler, Sampler};
+use crate::pbrt::scene::{Element, Material, RayKind, Scene};
+use crate::pbrt::vector3::Vector3;
+
+/// Classic Whitted ray tracer: point lights with hard shadows on diffuse surfaces and recursive
+/// perfect reflection and refraction. One ray per pixel, meant for quick previews.
+pub struct WhittedIntegrator {
+    pub max_depth: usize,
+}
+
+impl WhittedIntegrator {
+    pub fn new(max_depth: usize) -> WhittedIntegrator {
+        WhittedIntegrator { max_depth }
+    }
+
+    fn trace(
+        &self,
+        scene: &Scene,
+        segment: &Segment,
+        depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let Segment {
+            ref ray,
+            kind,
+            medium,
+            from,
+        } = *segment;
+        let intersection = match scene.trace_for(ray, kind) {
+            Some(intersection) => intersection,
+            None => return environment(scene, from, &ray.direction),
+        };
+
+        let element = intersection.element;
+        let hit_point = ray.origin + (ray.direction * intersection.distance);
+        let normal = element.surface_normal(&hit_point);
+        let wo = -ray.direction;
+
+        let color = match *element.material() {
+            Material::Diffuse { .. } | Material::Principled { .. } => {
+                match element.bsdf(&hit_point) {
+                    Some(bsdf) => sample_lights(scene, element, &hit_point, &bsdf, &wo, sampler),
+                    None => Color::black(),
+                }
+            }
+
+            // Polished metal and coats are followed like a mirror, rough ones only see the lights.
+            // Translucent boundaries are followed the same way, inside they only absorb.
+            Material::Conductor { .. } | Material::Coated { .. } | Material::Subsurface { .. } => {
+                let bsdf = match element.bsdf(&hit_point) {
+                    Some(bsdf) => bsdf,
+                    None => return Color::black(),
+                };
+                if bsdf.is_specular() {
+                    let next = scatter(element, &bsdf, &hit_point, &wo, medium, sampler);
+                    self.follow(scene, next, depth, sampler)
+                } else {
+                    sample_lights(scene, element, &hit_point, &bsdf, &wo, sampler)
+                }
+            }
+
+            Material::Emissive { .. } => {
+                if !is_linked(from, element.attributes().light_group) {
+                    return Color::black();
+                }
+                element.emitted_towards(&hit_point, &normal, &wo)
+            }
+
+            Material::Reflective => {
+                if depth >= self.max_depth {
+                    return Color::black();
+                }
+                let direction = make_reflection(ray.direction, normal);
+                let reflected = Segment {
+                    ray: spawn_ray(&hit_point, &normal, direction),
+                    kind: RayKind::Reflection,
+                    medium,
+                    from: Some(element),
+                };
+                self.trace(scene, &reflected, depth + 1, sampler)
+            }
+
+            Material::Refractive { index, film, .. } => {
+                if depth >= self.max_depth {
+                    return Color::black();
+                }
+
+                match element.bsdf(&hit_point) {
+                    // Frosted glass has no single direction to follow, one is picked at random.
+                    Some(ref bsdf) if !bsdf.is_specular() => {
+                        let next = scatter(element, bsdf, &hit_point, &wo, medium, sampler);
+                        self.follow(scene, next, depth, sampler)
+                    }
+                    _ => {
+                        let kr = match film {
+                            Some(film) => film.dielectric(wo.dot(&normal), index as f64),
+                            None => {
+                                Color::white()
+                                    * fresnel_dielectric(wo.dot(&normal), index as f64) as f32
+                            }
+                        };
+
+                        let reflection_direction = make_reflection(ray.direction, normal);
+                        let reflected = Segment {
+                            ray: spawn_ray(&hit_point, &normal, reflection_direction),
+                            kind: RayKind::Reflection,
+                            medium,
+                            from: Some(element),
+                        };
+                        let mut color = self.trace(scene, &reflected, depth + 1, sampler) * kr;
+
+                        if let Some(transmission) = Ray::create_transmission(
+                            normal,
+                            ray.direction,
+                            hit_point,
+                            FLOATING_POINT_BACKOFF,
+                            index,
+                        ) {
+                            let refracted = Segment {
+                                medium: medium_after(
+                                    element,
+                                    &normal,
+                                    &wo,
+                                    &transmission.direction,
+                                    medium,
+                                ),
+                                ray: transmission,
+                                kind: RayKind::Refraction,
+                                from: Some(element),
+                            };
+                            let transmitted = self.trace(scene, &refracted, depth + 1, sampler);
+                            let scale = refracted_radiance_scale(wo.dot(&normal), index as f64);
+                            color = color + transmitted * (Color::white() - kr) * scale;
+                        }
+
+                        color
+                    }
+                }
+            }
+        };
+
+        match medium {
+            Some(ref absorption) => color * beer_lambert(absorption, intersection.distance),
+            None => color,
+        }
+    }
+
+    fn follow(
+        &self,
+        scene: &Scene,
+        continuation: Option<Continuation>,
+        depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        match continuation {
+            Some(ref next) if depth < self.max_depth => {
+                self.trace(scene, &next.segment, depth + 1, sampler) * next.weight
+            }
+            _ => Color::black(),
+        }
+    }
+}
+
+// Ray to trace, with the absorption of the medium it travels through and the element it
+// leaves, `None` for the camera.
+struct Segment<'a> {
+    ray: Ray,
+    kind: RayKind,
+    medium: Option<Color>,
+    from: Option<&'a Element>,
+}
+
+// Segment continuing a path along a direction sampled from a BSDF, and its weight.
+struct Continuation<'a> {
+    segment: Segment<'a>,
+    weight: Color,
+}
+
+// Picks a single direction to follow from `bsdf`, for surfaces without one obvious direction.
+fn scatter<'a>(
+    element: &'a Element,
+    bsdf: &SurfaceBsdf,
+    hit_point: &Point,
+    wo: &Vector3,
+    medium: Option<Color>,
+    sampler: &mut dyn Sampler,
+) -> Option<Continuation<'a>> {
+    let normal = &bsdf.frame.normal;
+    let sample = bsdf.sample_f(wo, sampler)?;
+
+    Some(Continuation {
+        segment: Segment {
+            ray: spawn_ray(hit_point, normal, sample.wi),
+            kind: scattered_ray_kind(&sample, normal, wo),
+            medium: medium_after(element, normal, wo, &sample.wi, medium),
+            from: Some(element),
+        },
+        weight: sample.f * (sample.wi.dot(normal).abs() / sample.pdf) as f32,
+    })
+}
+
+impl Integrator for WhittedIntegrator {
+    fn render(&self, scene: &Scene) -> Film {
+        let mut sampler = RandomSampler::default();
+        let mut film = Film::new(scene.width, scene.height, EXPOSURE);
+
+        for y in 0..scene.height {
+            for x in 0..scene.width {
+                let primary = Segment {
+                    ray: Ray::create_prime(x, y, scene),
+                    kind: RayKind::Camera,
+                    medium: None,
+                    from: None,
+                };
+                film.add_sample(x, y, self.trace(scene, &primary, 0, &mut sampler));
+            }
+        }
+
+        film
+    }
+}
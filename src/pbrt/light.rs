@@ -0,0 +1,340 @@
+use image::hdr::HDRDecoder;
+use image::GenericImage;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use super::color::Color;
+use super::ies::IesProfile;
+use super::point::Point;
+use super::sampler::Sampler;
+use super::sampling::{
+    direction_to_equirect, sample_equirect, to_world, uniform_cone_pdf, uniform_sample_cone,
+    uniform_sample_disk, uniform_sample_sphere, Distribution2D,
+};
+use super::vector3::Vector3;
+
+/// Group of the lights illuminating every element unless linked otherwise.
+pub const DEFAULT_LIGHT_GROUP: u32 = 1;
+
+pub struct LightSample {
+    /// Radiance arriving at the receiver. Delta lights report irradiance with a pdf of one.
+    pub radiance: Color,
+    /// Unit direction from the receiver towards the light.
+    pub direction: Vector3,
+    /// Distance to the light along `direction`, infinite for distant lights.
+    pub distance: f64,
+    pub pdf: f64,
+}
+
+/// Ray leaving a light, as photons are emitted.
+pub struct LightEmission {
+    pub origin: Point,
+    /// Unit direction the light travels along.
+    pub direction: Vector3,
+    /// Emitted radiance over the densities the ray was picked with.
+    pub power: Color,
+}
+
+/// Light source that isn't part of the geometry, so it can only be reached by shadow rays.
+pub trait Light {
+    fn sample_incident(&self, point: &Point, sampler: &mut dyn Sampler) -> Option<LightSample>;
+
+    /// Ray carrying light towards a scene within `radius` of `center`. Lights at infinity, the
+    /// default, shine through a disk as wide as the scene, facing them.
+    fn sample_emission(
+        &self,
+        center: &Point,
+        radius: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightEmission> {
+        let sample = self.sample_incident(center, sampler)?;
+        if sample.pdf == 0.0 || sample.radiance.is_black() {
+            return None;
+        }
+
+        let (x, z) = uniform_sample_disk(sampler.get_2d());
+        let offset = Vector3 { x, y: 1.0, z } * radius;
+        let area = std::f64::consts::PI * radius * radius;
+        Some(LightEmission {
+            origin: *center + to_world(&offset, &sample.direction),
+            direction: -sample.direction,
+            power: sample.radiance * (area / sample.pdf) as f32,
+        })
+    }
+
+    /// Light groups the light belongs to, one bit per group. It only lights elements linked
+    /// to one of them.
+    fn light_group(&self) -> u32;
+
+    /// Radiance arriving along a ray that left the scene in `direction`, for lights at infinity.
+    fn le(&self, _direction: &Vector3) -> Color {
+        Color::black()
+    }
+}
+
+/// Light emitting `intensity` candela from a single point, in all directions unless a
+/// photometric `profile` is given, whose nadir is pointing down. The profile's candela are then
+/// multiplied by `intensity`.
+pub struct PointLight {
+    pub position: Point,
+    pub color: Color,
+    pub intensity: f32,
+    pub profile: Option<IesProfile>,
+    pub light_group: u32,
+}
+
+impl Light for PointLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, point: &Point, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let to_light = self.position - *point;
+        let distance2 = to_light.norm();
+        let distance = distance2.sqrt();
+        let direction = to_light * (1.0 / distance);
+
+        let scale = match self.profile {
+            Some(ref profile) => profile.intensity(&Vector3::down(), &-direction),
+            None => 1.0,
+        };
+        if scale == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            radiance: self.color * (self.intensity * scale as f32 / distance2 as f32),
+            direction,
+            distance,
+            pdf: 1.0,
+        })
+    }
+
+    fn sample_emission(
+        &self,
+        _center: &Point,
+        _radius: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightEmission> {
+        let direction = uniform_sample_sphere(sampler.get_2d());
+        let scale = match self.profile {
+            Some(ref profile) => profile.intensity(&Vector3::down(), &direction),
+            None => 1.0,
+        };
+        if scale == 0.0 {
+            return None;
+        }
+
+        Some(LightEmission {
+            origin: self.position,
+            direction,
+            power: self.color * (self.intensity * (scale * 4.0 * std::f64::consts::PI) as f32),
+        })
+    }
+}
+
+/// Point light restricted to a cone around `direction`. Angles are in degrees, measured from
+/// the axis: full intensity inside `falloff_angle`, fading out until `cone_angle`. A photometric
+/// `profile` has its nadir along `direction` and its candela multiplied by `intensity`.
+pub struct SpotLight {
+    pub position: Point,
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub cone_angle: f64,
+    pub falloff_angle: f64,
+    pub profile: Option<IesProfile>,
+    pub light_group: u32,
+}
+
+impl SpotLight {
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        let cos_total = self.cone_angle.to_radians().cos();
+        let cos_falloff = self.falloff_angle.to_radians().cos();
+
+        if cos_theta < cos_total {
+            0.0
+        } else if cos_theta >= cos_falloff {
+            1.0
+        } else {
+            let delta = (cos_theta - cos_total) / (cos_falloff - cos_total);
+            delta * delta * delta * delta
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, point: &Point, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let to_light = self.position - *point;
+        let distance2 = to_light.norm();
+        let distance = distance2.sqrt();
+        let direction = to_light * (1.0 / distance);
+
+        let mut falloff = self.falloff(-direction.dot(&self.direction.normalize()));
+        if let Some(ref profile) = self.profile {
+            falloff *= profile.intensity(&self.direction, &-direction);
+        }
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            radiance: self.color * (self.intensity * falloff as f32 / distance2 as f32),
+            direction,
+            distance,
+            pdf: 1.0,
+        })
+    }
+
+    fn sample_emission(
+        &self,
+        _center: &Point,
+        _radius: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightEmission> {
+        let cos_total = self.cone_angle.to_radians().cos();
+        let local = uniform_sample_cone(sampler.get_2d(), cos_total);
+        let direction = to_world(&local, &self.direction.normalize());
+
+        let mut falloff = self.falloff(local.y);
+        if let Some(ref profile) = self.profile {
+            falloff *= profile.intensity(&self.direction, &direction);
+        }
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightEmission {
+            origin: self.position,
+            direction,
+            power: self.color * (self.intensity * (falloff / uniform_cone_pdf(cos_total)) as f32),
+        })
+    }
+}
+
+/// Light arriving from infinitely far away along `direction`, like the sun. `intensity` is the
+/// irradiance on a surface facing it.
+pub struct DirectionalLight {
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub light_group: u32,
+}
+
+impl Light for DirectionalLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, _point: &Point, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        Some(LightSample {
+            radiance: self.color * self.intensity,
+            direction: -self.direction.normalize(),
+            distance: f64::INFINITY,
+            pdf: 1.0,
+        })
+    }
+}
+
+/// Infinitely far away light from an equirectangular image, importance sampled by luminance.
+pub struct EnvironmentLight {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    /// Rotation around the vertical axis, in degrees.
+    pub rotation: f64,
+    pub intensity: f32,
+    pub light_group: u32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentLight {
+    /// Loads a Radiance `.hdr` image, or any other format `image` reads as sRGB.
+    pub fn load(path: PathBuf, rotation: f64, intensity: f32) -> Result<EnvironmentLight, String> {
+        let error = || format!("Unable to open environment map: {:?}", &path);
+
+        let (width, height, pixels) = if path.extension().is_some_and(|e| e == "hdr") {
+            let file = File::open(&path).map_err(|_| error())?;
+            let decoder = HDRDecoder::new(BufReader::new(file)).map_err(|_| error())?;
+            let metadata = decoder.metadata();
+            let pixels: Vec<Color> = decoder
+                .read_image_hdr()
+                .map_err(|_| error())?
+                .iter()
+                .map(|p| Color {
+                    r: p.data[0],
+                    g: p.data[1],
+                    b: p.data[2],
+                })
+                .collect();
+            (metadata.width as usize, metadata.height as usize, pixels)
+        } else {
+            let image = image::open(&path).map_err(|_| error())?;
+            let pixels = image
+                .pixels()
+                .map(|(_, _, p)| Color::from_rgba(p))
+                .collect();
+            (image.width() as usize, image.height() as usize, pixels)
+        };
+
+        if width == 0 || height == 0 {
+            return Err(format!("Empty environment map: {:?}", &path));
+        }
+
+        // Rows near the poles cover less solid angle.
+        let func: Vec<f64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let theta = ((i / width) as f64 + 0.5) / height as f64 * std::f64::consts::PI;
+                p.luminance() as f64 * theta.sin()
+            })
+            .collect();
+
+        Ok(EnvironmentLight {
+            width,
+            height,
+            distribution: Distribution2D::new(&func, width, height),
+            pixels,
+            rotation,
+            intensity,
+            light_group: DEFAULT_LIGHT_GROUP,
+        })
+    }
+
+    fn lookup(&self, uv: (f64, f64)) -> Color {
+        let x = ((uv.0 * self.width as f64) as usize).min(self.width - 1);
+        let y = ((uv.1 * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, _point: &Point, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (direction, uv, pdf) = sample_equirect(
+            &self.distribution,
+            sampler.get_2d(),
+            self.rotation.to_radians(),
+        )?;
+
+        Some(LightSample {
+            radiance: self.lookup(uv),
+            direction,
+            distance: f64::INFINITY,
+            pdf,
+        })
+    }
+
+    fn le(&self, direction: &Vector3) -> Color {
+        self.lookup(direction_to_equirect(direction, self.rotation.to_radians()))
+    }
+}
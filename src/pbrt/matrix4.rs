@@ -8,7 +8,7 @@ pub struct Matrix4x4 {
 }
 
 impl Matrix4x4 {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     pub fn identity() -> Matrix4x4 {
         Matrix4x4 {
             elements: [[1.0, 0.0, 0.0, 0.0],
@@ -22,7 +22,7 @@ impl Matrix4x4 {
         Matrix4x4::scale(s, s, s)
     }
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     pub fn scale(sx: f64, sy: f64, sz: f64) -> Matrix4x4 {
         Matrix4x4 {
             elements: [[ sx, 0.0, 0.0, 0.0],
@@ -68,7 +68,7 @@ impl Matrix4x4 {
     //     }
     // }
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     pub fn translate(tx: f64, ty:f64, tz: f64) -> Matrix4x4 {
         Matrix4x4 {
             elements: [[1.0, 0.0, 0.0,  tx],
@@ -162,7 +162,7 @@ impl IndexMut<usize> for Matrix4x4 {
 impl Mul for Matrix4x4 {
     type Output = Matrix4x4;
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     fn mul(self, other: Matrix4x4) -> Matrix4x4 {
         let mut result = Matrix4x4::identity();
         for i in 0..4 {
@@ -181,12 +181,10 @@ impl Mul<Vector3> for Matrix4x4 {
     type Output = Vector3;
 
     fn mul(self, other: Vector3) -> Vector3 {
-        let result = Vector3 {
+        Vector3 {
             x: other.x * self[0][0] + other.y * self[0][1] + other.z * self[0][2] + self[0][3],
             y: other.x * self[1][0] + other.y * self[1][1] + other.z * self[1][2] + self[1][3],
             z: other.x * self[2][0] + other.y * self[2][1] + other.z * self[2][2] + self[2][3],
-        };
-
-        result
+        }
     }
 }
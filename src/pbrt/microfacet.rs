@@ -0,0 +1,176 @@
+use super::rendering::make_reflection;
+use super::sampling::uniform_sample_disk;
+use super::vector3::Vector3;
+
+// Below this alpha the surface is treated as perfectly smooth.
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals in the local shading frame, where
+/// `y` is the macro surface normal. Only the upper hemisphere is considered, callers mirror
+/// directions below the surface.
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Distribution for a perceptual `roughness` in `[0, 1]`, squared into alpha.
+    pub fn new(roughness: f32) -> TrowbridgeReitz {
+        let roughness = roughness.clamp(0.0, 1.0) as f64;
+
+        TrowbridgeReitz {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacets with normal `wm` per unit projected area.
+    pub fn d(&self, wm: &Vector3) -> f64 {
+        let cos2 = wm.y * wm.y;
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let alpha2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2 / alpha2;
+
+        1.0 / (std::f64::consts::PI * alpha2 * cos2 * cos2 * e * e)
+    }
+
+    // Smith's auxiliary function, the masked microfacet area over the visible one.
+    fn lambda(&self, w: &Vector3) -> f64 {
+        let cos2 = w.y * w.y;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of the microfacets visible from `w`.
+    pub fn g1(&self, w: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`, height correlated.
+    pub fn g(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals seen from `w`, which is what `sample_wm` draws from.
+    pub fn d_visible(&self, w: &Vector3, wm: &Vector3) -> f64 {
+        if w.y == 0.0 {
+            return 0.0;
+        }
+
+        self.g1(w) / w.y.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Microfacet normal visible from `w`, sampled by stretching the configuration to a
+    /// hemisphere and picking a point on its projected disk.
+    pub fn sample_wm(&self, w: &Vector3, u: (f64, f64)) -> Vector3 {
+        // Hemispherical configuration, with the normal along `y` and `w` above the surface.
+        let mut wh = Vector3 {
+            x: self.alpha * w.x,
+            y: w.y,
+            z: self.alpha * w.z,
+        }
+        .normalize();
+        if wh.y < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.y < 0.99999 {
+            Vector3::up().cross(&wh).normalize()
+        } else {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        };
+        let t2 = wh.cross(&t1);
+
+        // Warp the disk so that only the visible half of the hemisphere gets picked.
+        let (px, mut py) = uniform_sample_disk(u);
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let s = (1.0 + wh.y) * 0.5;
+        py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = t1 * px + t2 * py + wh * pz;
+
+        // Back to the ellipsoid configuration.
+        Vector3 {
+            x: self.alpha * nh.x,
+            y: nh.y.max(1e-6),
+            z: self.alpha * nh.z,
+        }
+        .normalize()
+    }
+
+    /// Direction reflected off a microfacet visible from `wo`, on the same side of the surface.
+    pub fn sample_reflection(&self, wo: &Vector3, u: (f64, f64)) -> Option<Vector3> {
+        if wo.y == 0.0 {
+            return None;
+        }
+
+        let wm = self.sample_wm(&upper(wo), u);
+        let mut wi = make_reflection(-upper(wo), wm);
+        if wi.y <= 0.0 {
+            return None;
+        }
+        if wo.y < 0.0 {
+            wi.y = -wi.y;
+        }
+        Some(wi)
+    }
+
+    /// Density of `sample_reflection` picking `wi`.
+    pub fn reflection_pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        let wm = match reflection_half_vector(wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+
+        // Reflecting about the normal halves the density of the outgoing directions.
+        self.d_visible(&upper(wo), &wm) / (4.0 * upper(wo).dot(&wm).abs())
+    }
+
+    /// Microfacet term of a reflection BRDF, `D G / (4 cos_o cos_i)`, to be multiplied by the
+    /// Fresnel term of the half vector.
+    pub fn reflection(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        match reflection_half_vector(wo, wi) {
+            Some(wm) => self.d(&wm) * self.g(wo, wi) / (4.0 * wo.y * wi.y).abs(),
+            None => 0.0,
+        }
+    }
+}
+
+/// Half vector of two directions on the same side of the surface, mirrored above it. `None` when
+/// they are on opposite sides or grazing.
+pub fn reflection_half_vector(wo: &Vector3, wi: &Vector3) -> Option<Vector3> {
+    if wo.y * wi.y <= 0.0 {
+        return None;
+    }
+
+    let wm = upper(wo) + upper(wi);
+    if wm.norm() == 0.0 {
+        return None;
+    }
+    Some(wm.normalize())
+}
+
+/// Mirrors a direction below the surface to the upper hemisphere, where the distribution is
+/// defined.
+pub fn upper(w: &Vector3) -> Vector3 {
+    Vector3 {
+        x: w.x,
+        y: w.y.abs(),
+        z: w.z,
+    }
+}
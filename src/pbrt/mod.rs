@@ -1,6 +1,17 @@
+pub mod bsdf;
+pub mod camera;
 pub mod color;
+pub mod film;
+pub mod ies;
+pub mod integrator;
+pub mod light;
 pub mod matrix4;
+pub mod microfacet;
 pub mod point;
 pub mod rendering;
+pub mod sampler;
+pub mod sampling;
 pub mod scene;
+pub mod sky;
+pub mod spectrum;
 pub mod vector3;
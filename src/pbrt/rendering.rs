@@ -1,5 +1,5 @@
 use super::point::Point;
-use super::scene::{Plane, Polygon, Scene, Sphere, TextureCoords};
+use super::scene::{barycentric, Plane, Polygon, Scene, Sphere, TextureCoords};
 use super::vector3::Vector3;
 
 #[derive(Debug, Clone, Copy)]
@@ -41,30 +41,45 @@ impl Ray {
         bias: f64,
         index: f32,
     ) -> Option<Ray> {
-        let mut ref_n = normal;
-        let mut eta_t = index as f64;
-        let mut eta_i = 1.0f64;
-        let mut i_dot_n = incident.dot(&normal);
-        if i_dot_n < 0.0 {
-            // outside of surface
-            i_dot_n = -i_dot_n;
+        let ref_n = if incident.dot(&normal) < 0.0 {
+            normal
         } else {
-            // inside surface; invert normal and swap he indicies of reflection
-            ref_n = -normal;
-            eta_t = 1.0f64;
-            eta_i = index as f64;
-        }
+            -normal
+        };
 
-        let eta = eta_i / eta_t;
-        let k = 1.0 - (eta * eta) * (1.0 - i_dot_n * i_dot_n);
-        if k < 0.0 {
-            None
-        } else {
-            Some(Ray {
-                origin: intersection + (ref_n * -bias),
-                direction: (incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt(),
-            })
-        }
+        refract(incident, normal, index).map(|direction| Ray {
+            origin: intersection + (ref_n * -bias),
+            direction,
+        })
+    }
+}
+
+pub fn make_reflection(incident: Vector3, normal: Vector3) -> Vector3 {
+    incident - normal * (2.0 * incident.dot(&normal))
+}
+
+/// Direction of `incident` after refraction, `None` on total internal reflection.
+pub fn refract(incident: Vector3, normal: Vector3, index: f32) -> Option<Vector3> {
+    let mut ref_n = normal;
+    let mut eta_t = index as f64;
+    let mut eta_i = 1.0f64;
+    let mut i_dot_n = incident.dot(&normal);
+    if i_dot_n < 0.0 {
+        // outside of surface
+        i_dot_n = -i_dot_n;
+    } else {
+        // inside surface; invert normal and swap he indicies of reflection
+        ref_n = -normal;
+        eta_t = 1.0f64;
+        eta_i = index as f64;
+    }
+
+    let eta = eta_i / eta_t;
+    let k = 1.0 - (eta * eta) * (1.0 - i_dot_n * i_dot_n);
+    if k < 0.0 {
+        None
+    } else {
+        Some((incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt())
     }
 }
 
@@ -124,7 +139,7 @@ impl Intersectable for Plane {
         let denom = normal.dot(&ray.direction);
         if denom > 1e-6 {
             let v = self.origin - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance >= 0.0 {
                 return Some(distance);
             }
@@ -222,26 +237,18 @@ impl Intersectable for Polygon {
         self.normal
     }
 
-    fn texture_coords(&self, _: &Point) -> TextureCoords {
-        // let mut x_axis = self.normal.cross(&Vector3 {
-        //   x: 0.0,
-        //   y: 0.0,
-        //   z: 1.0,
-        // });
-        // if x_axis.length() == 0.0 {
-        //   x_axis = self.normal.cross(&Vector3 {
-        //     x: 0.0,
-        //     y: 1.0,
-        //     z: 0.0,
-        //   });
-        // }
-        // let y_axis = self.normal.cross(&x_axis);
-
-        // let hit_vec = *hit_point - self.origin;
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (b1, b2) = barycentric(
+            &(self.vertices[1] - self.vertices[0]),
+            &(self.vertices[2] - self.vertices[0]),
+            &(Vector3::from_point(hit_point) - self.vertices[0]),
+        );
+        let b0 = 1.0 - b1 - b2;
+        let [t0, t1, t2] = self.texture_coords;
 
         TextureCoords {
-            x: 0.0 as f32,
-            y: 0.0 as f32,
+            x: (t0.x as f64 * b0 + t1.x as f64 * b1 + t2.x as f64 * b2) as f32,
+            y: (t0.y as f64 * b0 + t1.y as f64 * b1 + t2.y as f64 * b2) as f32,
         }
     }
 }
@@ -0,0 +1,58 @@
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+pub trait Sampler {
+    /// Switches to an independent sequence of samples, for samplers that keep several.
+    fn start_stream(&mut self, _index: usize) {}
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Default)]
+pub struct RandomSampler {
+    rng: ThreadRng,
+}
+
+impl Sampler for RandomSampler {
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+/// Sequence of samples fully determined by its seed, for estimates that must give the same
+/// result every time they are asked for the same thing.
+pub struct HashSampler {
+    state: u64,
+}
+
+impl HashSampler {
+    /// Seeded from every bit of `values`.
+    pub fn new(values: &[f64]) -> HashSampler {
+        let mut sampler = HashSampler { state: 0 };
+        for value in values {
+            sampler.state ^= value.to_bits();
+            sampler.state = sampler.next_u64();
+        }
+        sampler
+    }
+
+    // SplitMix64.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Sampler for HashSampler {
+    fn get_1d(&mut self) -> f64 {
+        // The top 53 bits, so that the value stays below one.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
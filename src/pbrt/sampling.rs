@@ -0,0 +1,322 @@
+use super::vector3::Vector3;
+
+pub fn create_coordinate_system(normal: &Vector3) -> (Vector3, Vector3) {
+    let n_t = if (normal.x.abs()) > (normal.y.abs()) {
+        Vector3 {
+            x: normal.z,
+            y: 0.0,
+            z: -normal.x,
+        }
+        .normalize()
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: -normal.z,
+            z: normal.y,
+        }
+        .normalize()
+    };
+    let n_b = normal.cross(&n_t);
+
+    (n_t, n_b)
+}
+
+/// Transforms a vector from the local frame (with `y` along the normal) to world space.
+pub fn to_world(local: &Vector3, normal: &Vector3) -> Vector3 {
+    let (n_t, n_b) = create_coordinate_system(normal);
+
+    Vector3 {
+        x: local.x * n_b.x + local.y * normal.x + local.z * n_t.x,
+        y: local.x * n_b.y + local.y * normal.y + local.z * n_t.y,
+        z: local.x * n_b.z + local.y * normal.z + local.z * n_t.z,
+    }
+}
+
+/// Cosine weighted direction in the local frame, `y` is up.
+pub fn cosine_sample_hemisphere(u: (f64, f64)) -> Vector3 {
+    let r = u.0.sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u.1;
+
+    Vector3 {
+        x: r * phi.cos(),
+        y: (1.0 - u.0).max(0.0).sqrt(),
+        z: r * phi.sin(),
+    }
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) * std::f64::consts::FRAC_1_PI
+}
+
+pub fn uniform_sample_sphere(u: (f64, f64)) -> Vector3 {
+    let y = 1.0 - 2.0 * u.0;
+    let r = (1.0 - y * y).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u.1;
+
+    Vector3 {
+        x: r * phi.cos(),
+        y,
+        z: r * phi.sin(),
+    }
+}
+
+/// Point uniformly distributed over the unit disk.
+pub fn uniform_sample_disk(u: (f64, f64)) -> (f64, f64) {
+    let r = u.0.sqrt();
+    let theta = 2.0 * std::f64::consts::PI * u.1;
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Barycentric coordinates of a point uniformly distributed over a triangle.
+pub fn uniform_sample_triangle(u: (f64, f64)) -> (f64, f64) {
+    let su0 = u.0.sqrt();
+    (1.0 - su0, u.1 * su0)
+}
+
+/// Piecewise constant distribution over `[0, 1)` built from a tabulated function.
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    cdf: Vec<f64>,
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[f64]) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+
+        let integral = cdf[n];
+        for (i, value) in cdf.iter_mut().enumerate().skip(1) {
+            *value = if integral == 0.0 {
+                i as f64 / n as f64
+            } else {
+                *value / integral
+            };
+        }
+
+        Distribution1D {
+            func: func.to_vec(),
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    // Index of the last cdf entry not above `u`.
+    fn find_interval(&self, u: f64) -> usize {
+        let index = self.cdf.partition_point(|&c| c <= u);
+        index.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// Index of the chosen segment and the probability of choosing it.
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let index = self.find_interval(u);
+        (index, self.discrete_pdf(index))
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> f64 {
+        if self.integral == 0.0 {
+            return 1.0 / self.count() as f64;
+        }
+        self.func[index].abs() / (self.integral * self.count() as f64)
+    }
+
+    /// Value in `[0, 1)` distributed proportionally to the function, with its density and the
+    /// segment it fell in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let index = self.find_interval(u);
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let du = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+
+        (
+            ((index as f64 + du) / self.count() as f64).min(1.0 - f64::EPSILON),
+            self.pdf(index),
+            index,
+        )
+    }
+
+    /// Density of the continuous distribution over the segment `index`.
+    pub fn pdf(&self, index: usize) -> f64 {
+        if self.integral == 0.0 {
+            return 1.0;
+        }
+        self.func[index].abs() / self.integral
+    }
+}
+
+/// Piecewise-constant distribution over `[0, 1)^2`, given as rows of function values.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal =
+            Distribution1D::new(&conditional.iter().map(|d| d.integral).collect::<Vec<f64>>());
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Point distributed proportionally to the function and its density.
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u.1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u.0);
+
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// Density of `sample_continuous` returning `uv`.
+    pub fn pdf(&self, uv: (f64, f64)) -> f64 {
+        let rows = self.marginal.count();
+        let row = ((uv.1 * rows as f64).max(0.0) as usize).min(rows - 1);
+        let conditional = &self.conditional[row];
+        let columns = conditional.count();
+        let column = ((uv.0 * columns as f64).max(0.0) as usize).min(columns - 1);
+
+        conditional.pdf(column) * self.marginal.pdf(row)
+    }
+}
+
+/// Texture coordinates of `direction` in an equirectangular map turned by `rotation` radians
+/// around the vertical axis, `v` going from the zenith down.
+pub fn direction_to_equirect(direction: &Vector3, rotation: f64) -> (f64, f64) {
+    let d = direction.normalize();
+    let u = (d.z.atan2(d.x) + rotation) / (2.0 * std::f64::consts::PI);
+
+    (
+        u - u.floor(),
+        d.y.clamp(-1.0, 1.0).acos() / std::f64::consts::PI,
+    )
+}
+
+pub fn equirect_to_direction(uv: (f64, f64), rotation: f64) -> Vector3 {
+    let theta = uv.1 * std::f64::consts::PI;
+    let phi = uv.0 * 2.0 * std::f64::consts::PI - rotation;
+
+    Vector3 {
+        x: theta.sin() * phi.cos(),
+        y: theta.cos(),
+        z: theta.sin() * phi.sin(),
+    }
+}
+
+/// Samples a direction proportionally to an equirectangular map, returning its texture
+/// coordinates and solid angle density.
+pub fn sample_equirect(
+    distribution: &Distribution2D,
+    u: (f64, f64),
+    rotation: f64,
+) -> Option<(Vector3, (f64, f64), f64)> {
+    let (uv, map_pdf) = distribution.sample_continuous(u);
+    let sin_theta = (uv.1 * std::f64::consts::PI).sin();
+    if map_pdf == 0.0 || sin_theta == 0.0 {
+        return None;
+    }
+
+    // From the unit square to the sphere of directions.
+    let pdf = map_pdf / (2.0 * std::f64::consts::PI * std::f64::consts::PI * sin_theta);
+    Some((equirect_to_direction(uv, rotation), uv, pdf))
+}
+
+/// Direction uniformly distributed in the cone of half-angle `acos(cos_max)` around y.
+pub fn uniform_sample_cone(u: (f64, f64), cos_max: f64) -> Vector3 {
+    let cos_theta = (1.0 - u.0) + u.0 * cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = u.1 * 2.0 * std::f64::consts::PI;
+
+    Vector3 {
+        x: sin_theta * phi.cos(),
+        y: cos_theta,
+        z: sin_theta * phi.sin(),
+    }
+}
+
+pub fn uniform_cone_pdf(cos_max: f64) -> f64 {
+    1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discrete_pdfs_sum_to_one() {
+        let distribution = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+
+        let total: f64 = (0..distribution.count())
+            .map(|i| distribution.discrete_pdf(i))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        assert_eq!(distribution.discrete_pdf(1), 0.0);
+        assert_eq!(distribution.sample_discrete(0.1), (0, 0.125));
+        assert_eq!(distribution.sample_discrete(0.4), (2, 0.375));
+    }
+
+    #[test]
+    fn continuous_sample_matches_pdf() {
+        let distribution = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+        assert_eq!(distribution.integral, 2.0);
+
+        for i in 0..100 {
+            let (x, pdf, index) = distribution.sample_continuous((i as f64 + 0.5) / 100.0);
+            assert_eq!(index, (x * 4.0) as usize);
+            assert_ne!(index, 1);
+            assert_eq!(pdf, distribution.pdf(index));
+        }
+
+        // The density integrates to one over [0, 1).
+        let total: f64 = (0..4).map(|i| distribution.pdf(i) / 4.0).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_function_is_uniform() {
+        let distribution = Distribution1D::new(&[0.0; 4]);
+
+        assert_eq!(distribution.sample_discrete(0.6), (2, 0.25));
+        let (x, pdf, _) = distribution.sample_continuous(0.3);
+        assert!((x - 0.3).abs() < 1e-12);
+        assert_eq!(pdf, 1.0);
+    }
+
+    #[test]
+    fn two_dimensional_sample_matches_pdf() {
+        let func = [1.0, 2.0, 0.0, 3.0, 4.0, 5.0];
+        let distribution = Distribution2D::new(&func, 3, 2);
+
+        for i in 0..10 {
+            for j in 0..10 {
+                let u = ((i as f64 + 0.5) / 10.0, (j as f64 + 0.5) / 10.0);
+                let (uv, pdf) = distribution.sample_continuous(u);
+                assert!(pdf > 0.0);
+                assert!((pdf - distribution.pdf(uv)).abs() < 1e-12);
+            }
+        }
+
+        // Density proportional to the function, normalized over the unit square.
+        let total: f64 = func.iter().sum::<f64>() / 6.0;
+        assert!((distribution.pdf((0.9, 0.9)) - 5.0 / total).abs() < 1e-12);
+        assert_eq!(distribution.pdf((0.9, 0.2)), 0.0);
+    }
+}
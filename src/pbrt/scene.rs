@@ -1,10 +1,19 @@
 use image::{DynamicImage, GenericImage};
 use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
+use super::bsdf::{
+    Bsdf, Conductor, Lambertian, Layered, OrenNayar, Principled, RoughDielectric,
+    SpecularDielectric, SpecularReflection, SurfaceBsdf, ThinFilm,
+};
 use super::color::Color;
+use super::light::{Light, DEFAULT_LIGHT_GROUP};
+use super::microfacet::TrowbridgeReitz;
 use super::point::Point;
 use super::rendering::{Intersectable, Ray};
+use super::sampling::{uniform_sample_sphere, uniform_sample_triangle};
+use super::spectrum::{blackbody_color, Dispersion, Spectrum};
 use super::vector3::Vector3;
 
 pub struct Texture {
@@ -26,8 +35,16 @@ impl Texture {
             Err(format!("Unable to open texture file: {:?}", &path))
         }
     }
+
+    fn texel(&self, texture_coords: &TextureCoords) -> image::Rgba<u8> {
+        let tex_x = wrap(texture_coords.x, self.texture.width());
+        let tex_y = wrap(texture_coords.y, self.texture.height());
+
+        self.texture.get_pixel(tex_x, tex_y)
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
@@ -54,34 +71,548 @@ impl Coloration {
     pub fn color(&self, texture_coords: &TextureCoords) -> Color {
         match *self {
             Coloration::Color(color) => color,
-            Coloration::Texture(ref texture) => {
-                let tex_x = wrap(texture_coords.x, texture.texture.width());
-                let tex_y = wrap(texture_coords.y, texture.texture.height());
+            Coloration::Texture(ref texture) => Color::from_rgba(texture.texel(texture_coords)),
+        }
+    }
 
-                Color::from_rgba(texture.texture.get_pixel(tex_x, tex_y))
+    /// Luminance averaged over all the texels of a texture.
+    pub fn average_luminance(&self) -> f32 {
+        match *self {
+            Coloration::Color(color) => color.luminance(),
+            Coloration::Texture(ref texture) => {
+                let (width, height) = texture.texture.dimensions();
+                let total = texture.texture.pixels().fold(0.0, |acc, (_, _, rgba)| {
+                    acc + Color::from_rgba(rgba).luminance()
+                });
+                total / (width * height).max(1) as f32
             }
         }
     }
 }
 
+/// A single material parameter, constant or read from the red channel of a texture. Texels are
+/// taken as linear values in `[0, 1]`.
+#[derive(Debug)]
+pub enum Scalar {
+    Value(f32),
+    // Boxed so that materials with many parameters stay small.
+    Texture(Box<Texture>),
+}
+
+impl Scalar {
+    pub fn value(&self, texture_coords: &TextureCoords) -> f32 {
+        match *self {
+            Scalar::Value(value) => value,
+            Scalar::Texture(ref texture) => texture.texel(texture_coords).data[0] as f32 / 255.0,
+        }
+    }
+}
+
+/// Barycentric coordinates of the point at `offset` from the first vertex of a triangle with
+/// edges `e1` and `e2`, as the weights of the second and third vertex.
+pub fn barycentric(e1: &Vector3, e2: &Vector3, offset: &Vector3) -> (f64, f64) {
+    let d00 = e1.dot(e1);
+    let d01 = e1.dot(e2);
+    let d11 = e2.dot(e2);
+    let d20 = offset.dot(e1);
+    let d21 = offset.dot(e2);
+    let denom = d00 * d11 - d01 * d01;
+    if denom == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    (
+        (d11 * d20 - d01 * d21) / denom,
+        (d00 * d21 - d01 * d20) / denom,
+    )
+}
+
 #[derive(Debug)]
 pub enum Material {
-    Diffuse { albedo: f32, color: Coloration },
+    /// Matte surface, Lambertian or Oren-Nayar with a `sigma` above zero, the standard deviation
+    /// of the microfacet slopes in degrees.
+    Diffuse {
+        albedo: f32,
+        color: Coloration,
+        sigma: f32,
+    },
     Reflective,
-    Refractive { index: f32 },
-    Emissive { emission: Color, intensity: f32 },
+    /// Glass, frosted by GGX microfacets when `roughness` is above zero. Light travelling
+    /// inside is attenuated by the `absorption` coefficient per unit distance. With
+    /// `dispersion`, paths following a single wavelength refract by its own index instead of
+    /// `index`. A thin `film` on the surface makes its reflection iridescent.
+    Refractive {
+        index: f32,
+        roughness: Scalar,
+        absorption: Color,
+        dispersion: Option<Dispersion>,
+        film: Option<ThinFilm>,
+    },
+    /// Metal with complex index of refraction `eta + i k` and GGX roughness, optionally coated
+    /// by a thin `film`.
+    Conductor {
+        eta: Color,
+        k: Color,
+        roughness: Scalar,
+        film: Option<ThinFilm>,
+    },
+    /// Clear coat of index of refraction `index` and GGX `roughness` over any `base`, which is
+    /// treated as opaque. The coat is `thickness` thick and absorbs `absorption` per unit
+    /// distance.
+    Coated {
+        base: Box<Material>,
+        index: f32,
+        roughness: Scalar,
+        absorption: Color,
+        thickness: f32,
+    },
+    /// Translucent material like wax, marble or skin. Light refracted through its smooth
+    /// boundary of index `index` scatters inside, travelling `mean_free_path` on average between
+    /// two scattering events and keeping `albedo` of its power at each, until it leaves again.
+    /// Needs a closed surface. Only the path integrator follows the scattering, the others
+    /// just see the absorption.
+    Subsurface {
+        index: f32,
+        albedo: Color,
+        mean_free_path: Color,
+    },
+    /// Principled surface, see `bsdf::Principled` for the meaning of the parameters.
+    Principled {
+        base_color: Coloration,
+        metallic: Scalar,
+        roughness: Scalar,
+        specular: Scalar,
+        sheen: Scalar,
+        clearcoat: Scalar,
+        transmission: Scalar,
+        subsurface: Scalar,
+    },
+    /// Light source of radiance `emission` times `intensity`. With a `spectrum`, spectral
+    /// rendering uses its shape instead of upsampling the color of `emission`.
+    Emissive {
+        emission: Coloration,
+        intensity: f32,
+        two_sided: bool,
+        spectrum: Option<Spectrum>,
+    },
+}
+
+/// Measured metals, with their complex index of refraction sampled at the red, green and blue
+/// primaries.
+#[derive(Clone, Copy, Debug)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminum,
+    Silver,
+}
+
+impl Metal {
+    /// Real and imaginary parts of the index of refraction, `(eta, k)`.
+    pub fn ior(&self) -> (Color, Color) {
+        let (eta, k) = match *self {
+            Metal::Gold => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+            Metal::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            Metal::Aluminum => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            Metal::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        };
+        let color = |c: [f32; 3]| Color {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+        };
+
+        (color(eta), color(k))
+    }
+}
+
+impl FromStr for Metal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Metal, String> {
+        match s {
+            "gold" => Ok(Metal::Gold),
+            "copper" => Ok(Metal::Copper),
+            "aluminum" => Ok(Metal::Aluminum),
+            "silver" => Ok(Metal::Silver),
+            _ => Err(format!("Unknown metal: {}", s)),
+        }
+    }
+}
+
+// Luminous efficacy of monochromatic light at 555 nm, the peak of the eye's sensitivity.
+const LUMENS_PER_WATT: f32 = 683.0;
+
+/// Total power sent out by an emissive element.
+#[derive(Clone, Copy, Debug)]
+pub enum Power {
+    Lumens(f32),
+    /// Radiant watts, converted to lumens at 683 lm/W.
+    Watts(f32),
+}
+
+impl Power {
+    pub fn lumens(&self) -> f32 {
+        match *self {
+            Power::Lumens(lumens) => lumens,
+            Power::Watts(watts) => watts * LUMENS_PER_WATT,
+        }
+    }
+}
+
+impl FromStr for Power {
+    type Err = String;
+
+    /// Parses a number followed by `lm` or `W`, like `800lm` or `60W`.
+    fn from_str(s: &str) -> Result<Power, String> {
+        let s = s.trim();
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid power: {}", s))
+        };
+
+        if let Some(lumens) = s.strip_suffix("lm") {
+            parse(lumens).map(Power::Lumens)
+        } else if let Some(watts) = s.strip_suffix('W') {
+            parse(watts).map(Power::Watts)
+        } else {
+            Err(format!("Power needs a unit, lm or W: {}", s))
+        }
+    }
+}
+
+// Boundary of a dielectric, smooth or frosted depending on `distribution`.
+fn dielectric(index: f32, distribution: TrowbridgeReitz, film: Option<ThinFilm>) -> Box<dyn Bsdf> {
+    if distribution.is_smooth() {
+        Box::new(SpecularDielectric { index, film })
+    } else {
+        Box::new(RoughDielectric {
+            index,
+            distribution,
+            film,
+        })
+    }
+}
+
+impl Material {
+    /// Emission of a blackbody at `kelvin`, `intensity` being its luminance.
+    pub fn blackbody(kelvin: f64, intensity: f32, two_sided: bool) -> Material {
+        Material::Emissive {
+            emission: Coloration::Color(blackbody_color(kelvin)),
+            intensity,
+            two_sided,
+            spectrum: Some(Spectrum::blackbody(kelvin)),
+        }
+    }
+
+    /// Absorption coefficient leaving `transmittance` of the light after `distance` inside a
+    /// medium.
+    pub fn absorption_for(transmittance: Color, distance: f32) -> Color {
+        let coefficient = |channel: f32| -channel.max(1e-6).ln() / distance;
+
+        Color {
+            r: coefficient(transmittance.r),
+            g: coefficient(transmittance.g),
+            b: coefficient(transmittance.b),
+        }
+    }
+
+    /// Translucent material scattering `scattering` and absorbing `absorption` per unit
+    /// distance.
+    pub fn subsurface(index: f32, scattering: Color, absorption: Color) -> Material {
+        let extinction = scattering + absorption;
+        let ratio = |numerator: f32, denominator: f32| {
+            if denominator > 0.0 {
+                numerator / denominator
+            } else {
+                0.0
+            }
+        };
+
+        Material::Subsurface {
+            index,
+            albedo: Color {
+                r: ratio(scattering.r, extinction.r),
+                g: ratio(scattering.g, extinction.g),
+                b: ratio(scattering.b, extinction.b),
+            },
+            mean_free_path: Color {
+                r: ratio(1.0, extinction.r),
+                g: ratio(1.0, extinction.g),
+                b: ratio(1.0, extinction.b),
+            },
+        }
+    }
+
+    pub fn conductor(metal: Metal, roughness: Scalar, film: Option<ThinFilm>) -> Material {
+        let (eta, k) = metal.ior();
+
+        Material::Conductor {
+            eta,
+            k,
+            roughness,
+            film,
+        }
+    }
+
+    /// Scattering at `texture_coords` of light at `wavelength` nanometers, or of all the visible
+    /// spectrum when `None`. `None` for emitters which don't reflect any light.
+    pub fn bsdf(
+        &self,
+        texture_coords: &TextureCoords,
+        wavelength: Option<f64>,
+    ) -> Option<Box<dyn Bsdf>> {
+        match *self {
+            Material::Diffuse {
+                ref color,
+                albedo,
+                sigma,
+            } => {
+                // Same reflected power as the path integrator, `albedo * PI` over `PI`.
+                let color = color.color(texture_coords) * albedo;
+                if sigma > 0.0 {
+                    Some(Box::new(OrenNayar::new(color, sigma)))
+                } else {
+                    Some(Box::new(Lambertian { color }))
+                }
+            }
+            Material::Reflective => Some(Box::new(SpecularReflection)),
+            Material::Refractive {
+                index,
+                ref roughness,
+                dispersion,
+                film,
+                ..
+            } => {
+                let index = match (dispersion, wavelength) {
+                    (Some(dispersion), Some(wavelength)) => dispersion.index(wavelength) as f32,
+                    _ => index,
+                };
+                let distribution = TrowbridgeReitz::new(roughness.value(texture_coords));
+                Some(dielectric(index, distribution, film))
+            }
+            Material::Subsurface { index, .. } => {
+                Some(Box::new(SpecularDielectric { index, film: None }))
+            }
+            Material::Coated {
+                ref base,
+                index,
+                ref roughness,
+                absorption,
+                thickness,
+            } => {
+                let distribution = TrowbridgeReitz::new(roughness.value(texture_coords));
+                Some(Box::new(Layered {
+                    coat: dielectric(index, distribution, None),
+                    base: base.bsdf(texture_coords, wavelength)?,
+                    absorption,
+                    thickness,
+                }))
+            }
+            Material::Conductor {
+                eta,
+                k,
+                ref roughness,
+                film,
+            } => Some(Box::new(Conductor {
+                eta,
+                k,
+                distribution: TrowbridgeReitz::new(roughness.value(texture_coords)),
+                film,
+            })),
+            Material::Principled {
+                ref base_color,
+                ref metallic,
+                ref roughness,
+                ref specular,
+                ref sheen,
+                ref clearcoat,
+                ref transmission,
+                ref subsurface,
+            } => Some(Box::new(Principled {
+                base_color: base_color.color(texture_coords),
+                metallic: metallic.value(texture_coords),
+                roughness: roughness.value(texture_coords),
+                specular: specular.value(texture_coords),
+                sheen: sheen.value(texture_coords),
+                clearcoat: clearcoat.value(texture_coords),
+                transmission: transmission.value(texture_coords),
+                subsurface: subsurface.value(texture_coords),
+            })),
+            Material::Emissive { .. } => None,
+        }
+    }
+
+    /// Radiance leaving an emissive surface at `texture_coords`, black for everything else.
+    pub fn emitted(&self, texture_coords: &TextureCoords) -> Color {
+        match *self {
+            Material::Emissive {
+                ref emission,
+                intensity,
+                ..
+            } => emission.color(texture_coords) * intensity,
+            _ => Color::black(),
+        }
+    }
+
+    /// Luminance of the emitted radiance averaged over the emission texture.
+    pub fn average_emitted_luminance(&self) -> f32 {
+        match *self {
+            Material::Emissive {
+                ref emission,
+                intensity,
+                ..
+            } => emission.average_luminance() * intensity,
+            _ => 0.0,
+        }
+    }
+
+    /// Radiance leaving a surface with `normal` along `direction`. One-sided emitters are black
+    /// from behind.
+    pub fn emitted_towards(
+        &self,
+        texture_coords: &TextureCoords,
+        normal: &Vector3,
+        direction: &Vector3,
+    ) -> Color {
+        if self.is_two_sided() || normal.dot(direction) > 0.0 {
+            self.emitted(texture_coords)
+        } else {
+            Color::black()
+        }
+    }
+
+    pub fn is_two_sided(&self) -> bool {
+        matches!(
+            *self,
+            Material::Emissive {
+                two_sided: true,
+                ..
+            }
+        )
+    }
+
+    /// Absorption coefficient of the interior of a dielectric or translucent material, `None`
+    /// when it is clear or for any other material.
+    pub fn absorption(&self) -> Option<Color> {
+        match *self {
+            Material::Refractive { absorption, .. } if !absorption.is_black() => Some(absorption),
+            Material::Subsurface {
+                albedo,
+                mean_free_path,
+                ..
+            } => {
+                let coefficient =
+                    |albedo: f32, distance: f32| (1.0 - albedo).max(0.0) / distance.max(1e-6);
+
+                Some(Color {
+                    r: coefficient(albedo.r, mean_free_path.r),
+                    g: coefficient(albedo.g, mean_free_path.g),
+                    b: coefficient(albedo.b, mean_free_path.b),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Spectrum of the light sent out by an emitter, when it has its own.
+    pub fn emission_spectrum(&self) -> Option<&Spectrum> {
+        match *self {
+            Material::Emissive {
+                spectrum: Some(ref spectrum),
+                ..
+            } => Some(spectrum),
+            _ => None,
+        }
+    }
+
+    /// Whether the material scatters each wavelength differently.
+    pub fn is_dispersive(&self) -> bool {
+        matches!(
+            *self,
+            Material::Refractive {
+                dispersion: Some(_),
+                ..
+            }
+        )
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        matches!(*self, Material::Emissive { .. })
+    }
+}
+
+/// What a ray was spawned for, which decides the elements it can hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Reflection,
+    Refraction,
+    /// Any ray leaving a diffuse surface or an emitter.
+    Indirect,
+}
+
+/// Which rays see an element and which lights illuminate it.
+#[derive(Clone, Copy, Debug)]
+pub struct Attributes {
+    pub camera: bool,
+    pub shadows: bool,
+    pub reflections: bool,
+    pub refractions: bool,
+    pub indirect: bool,
+    /// Light groups an emissive element belongs to, one bit per group.
+    pub light_group: u32,
+    /// Light groups allowed to light the element directly.
+    pub light_links: u32,
+}
+
+impl Default for Attributes {
+    fn default() -> Attributes {
+        Attributes {
+            camera: true,
+            shadows: true,
+            reflections: true,
+            refractions: true,
+            indirect: true,
+            light_group: DEFAULT_LIGHT_GROUP,
+            light_links: !0,
+        }
+    }
+}
+
+impl Attributes {
+    pub fn is_visible_to(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadows,
+            RayKind::Reflection => self.reflections,
+            RayKind::Refraction => self.refractions,
+            RayKind::Indirect => self.indirect,
+        }
+    }
+
+    /// Whether lights of `group` illuminate the element.
+    pub fn is_lit_by(&self, group: u32) -> bool {
+        self.light_links & group != 0
+    }
 }
 
 pub struct Polygon {
     pub vertices: [Vector3; 3],
     pub normal: Vector3,
+    pub texture_coords: [TextureCoords; 3],
     pub material: Material,
+    pub attributes: Attributes,
 }
 
 pub struct Plane {
     pub origin: Point,
     pub normal: Vector3,
     pub material: Material,
+    pub attributes: Attributes,
 }
 
 pub enum Element {
@@ -91,6 +622,63 @@ pub enum Element {
 }
 
 impl Element {
+    /// Scattering at `point` on the element, in the frame of its surface normal there.
+    pub fn bsdf(&self, point: &Point) -> Option<SurfaceBsdf> {
+        self.bsdf_at_wavelength(point, None)
+    }
+
+    /// Like `bsdf`, for a path carrying a single `wavelength`, see `Material::bsdf`.
+    pub fn bsdf_at_wavelength(
+        &self,
+        point: &Point,
+        wavelength: Option<f64>,
+    ) -> Option<SurfaceBsdf> {
+        self.material()
+            .bsdf(&self.texture_coords(point), wavelength)
+            .map(|bsdf| SurfaceBsdf::new(self.surface_normal(point), bsdf))
+    }
+
+    /// Radiance emitted at `point` on the element, see `Material::emitted`.
+    pub fn emitted(&self, point: &Point) -> Color {
+        self.material().emitted(&self.texture_coords(point))
+    }
+
+    /// Radiance emitted at `point` along `direction`, see `Material::emitted_towards`.
+    pub fn emitted_towards(&self, point: &Point, normal: &Vector3, direction: &Vector3) -> Color {
+        self.material()
+            .emitted_towards(&self.texture_coords(point), normal, direction)
+    }
+
+    /// Sets the intensity of an emissive element so it sends out `power` in total, spread
+    /// over its surface. Textured emission keeps its pattern, scaled by its average
+    /// luminance.
+    pub fn set_power(&mut self, power: Power) -> Result<(), String> {
+        let area = self
+            .area()
+            .ok_or_else(|| String::from("Unbounded elements can't have a total power"))?;
+
+        match *self.material_mut() {
+            Material::Emissive {
+                ref emission,
+                ref mut intensity,
+                two_sided,
+                ..
+            } => {
+                let luminance = emission.average_luminance();
+                if luminance <= 0.0 {
+                    return Err(String::from("Black emitters can't have a total power"));
+                }
+
+                // A lambertian emitter of radiance L sends out L * PI per unit area and side.
+                let sides = if two_sided { 2.0 } else { 1.0 };
+                *intensity =
+                    power.lumens() / (std::f32::consts::PI * sides * area as f32 * luminance);
+                Ok(())
+            }
+            _ => Err(String::from("Only emissive elements have a power")),
+        }
+    }
+
     pub fn material(&self) -> &Material {
         match *self {
             Element::Sphere(ref s) => &s.material,
@@ -98,30 +686,75 @@ impl Element {
             Element::Polygon(ref p) => &p.material,
         }
     }
+
+    pub fn attributes(&self) -> &Attributes {
+        match *self {
+            Element::Sphere(ref s) => &s.attributes,
+            Element::Plane(ref p) => &p.attributes,
+            Element::Polygon(ref p) => &p.attributes,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        match *self {
+            Element::Sphere(ref mut s) => &mut s.material,
+            Element::Plane(ref mut p) => &mut p.material,
+            Element::Polygon(ref mut p) => &mut p.material,
+        }
+    }
+
+    /// Surface area of the element, `None` for unbounded shapes.
+    pub fn area(&self) -> Option<f64> {
+        match *self {
+            Element::Sphere(ref s) => Some(4.0 * std::f64::consts::PI * s.radius * s.radius),
+            Element::Plane(_) => None,
+            Element::Polygon(ref p) => {
+                let a = p.vertices[1] - p.vertices[0];
+                let b = p.vertices[2] - p.vertices[0];
+                Some(a.cross(&b).length() * 0.5)
+            }
+        }
+    }
+
+    /// Point uniformly distributed over the surface together with its normal.
+    pub fn sample_point(&self, u: (f64, f64)) -> Option<(Point, Vector3)> {
+        match *self {
+            Element::Sphere(ref s) => {
+                let normal = uniform_sample_sphere(u);
+                Some((s.center + normal * s.radius, normal))
+            }
+            Element::Plane(_) => None,
+            Element::Polygon(ref p) => {
+                let (b0, b1) = uniform_sample_triangle(u);
+                let v = p.vertices[0] * b0 + p.vertices[1] * b1 + p.vertices[2] * (1.0 - b0 - b1);
+                Some((Point::new(v.x, v.y, v.z), p.normal))
+            }
+        }
+    }
 }
 
 impl Intersectable for Element {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         match *self {
-            Element::Sphere(ref s) => s.intersect(&ray),
-            Element::Plane(ref p) => p.intersect(&ray),
-            Element::Polygon(ref p) => p.intersect(&ray),
+            Element::Sphere(ref s) => s.intersect(ray),
+            Element::Plane(ref p) => p.intersect(ray),
+            Element::Polygon(ref p) => p.intersect(ray),
         }
     }
 
     fn surface_normal(&self, hit_point: &Point) -> Vector3 {
         match *self {
-            Element::Sphere(ref s) => s.surface_normal(&hit_point),
-            Element::Plane(ref p) => p.surface_normal(&hit_point),
-            Element::Polygon(ref p) => p.surface_normal(&hit_point),
+            Element::Sphere(ref s) => s.surface_normal(hit_point),
+            Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Polygon(ref p) => p.surface_normal(hit_point),
         }
     }
 
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
         match *self {
-            Element::Sphere(ref s) => s.texture_coords(&hit_point),
-            Element::Plane(ref p) => p.texture_coords(&hit_point),
-            Element::Polygon(ref p) => p.texture_coords(&hit_point),
+            Element::Sphere(ref s) => s.texture_coords(hit_point),
+            Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Polygon(ref p) => p.texture_coords(hit_point),
         }
     }
 }
@@ -130,6 +763,7 @@ pub struct Sphere {
     pub center: Point,
     pub radius: f64,
     pub material: Material,
+    pub attributes: Attributes,
 }
 
 pub struct Intersection<'a> {
@@ -148,12 +782,39 @@ pub struct Scene {
     pub height: u32,
     pub fov: f64,
     pub entities: Vec<Element>,
+    pub lights: Vec<Box<dyn Light>>,
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
+    /// Corners of the box enclosing the bounded geometry and the origins of the planes.
+    pub fn bounds(&self) -> (Point, Point) {
+        let mut min = Point::from_one(f64::INFINITY);
+        let mut max = Point::from_one(f64::NEG_INFINITY);
+        let mut include = |p: Point, r: f64| {
+            min = Point::new(min.x.min(p.x - r), min.y.min(p.y - r), min.z.min(p.z - r));
+            max = Point::new(max.x.max(p.x + r), max.y.max(p.y + r), max.z.max(p.z + r));
+        };
+
+        for element in &self.entities {
+            match *element {
+                Element::Sphere(ref s) => include(s.center, s.radius),
+                Element::Plane(ref p) => include(p.origin, 0.0),
+                Element::Polygon(ref p) => {
+                    for v in &p.vertices {
+                        include(Point::new(v.x, v.y, v.z), 0.0);
+                    }
+                }
+            }
+        }
+
+        (min, max)
+    }
+
+    /// Closest hit among the elements visible to rays of `kind`.
+    pub fn trace_for(&self, ray: &Ray, kind: RayKind) -> Option<Intersection<'_>> {
         self.entities
             .iter()
+            .filter(|s| s.attributes().is_visible_to(kind))
             .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s)))
             .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
     }
@@ -0,0 +1,316 @@
+use super::color::Color;
+use super::light::{Light, LightSample, DEFAULT_LIGHT_GROUP};
+use super::point::Point;
+use super::sampler::Sampler;
+use super::sampling::{
+    equirect_to_direction, sample_equirect, to_world, uniform_cone_pdf, uniform_sample_cone,
+    Distribution2D,
+};
+use super::vector3::Vector3;
+
+// Resolution of the table the sky is importance sampled with.
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Illuminance of the sun outside the atmosphere in klux, the unit the sky luminance is in.
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+const SUN_ANGULAR_RADIUS: f64 = 0.2665;
+
+// Wavelengths in micrometers the red, green and blue sun transmittance is evaluated at.
+const WAVELENGTHS: [f64; 3] = [0.680, 0.550, 0.440];
+
+/// Preetham daylight sky over a diffuse ground, lit by the sun returned by `sun`. Radiance is
+/// in kcd/m² scaled by `intensity`.
+pub struct SkyLight {
+    /// Unit direction towards the sun.
+    pub sun_direction: Vector3,
+    pub intensity: f32,
+    pub light_group: u32,
+    model: Preetham,
+    sun_radiance: Color,
+    ground_radiance: Color,
+    distribution: Distribution2D,
+}
+
+impl SkyLight {
+    pub fn new(
+        sun_direction: Vector3,
+        turbidity: f64,
+        ground_albedo: Color,
+        intensity: f32,
+    ) -> SkyLight {
+        let sun_direction = sun_direction.normalize();
+        let model = Preetham::new(sun_direction, turbidity);
+        let sun_radiance = sun_radiance(model.theta_sun, turbidity, sun_direction.y > 0.0);
+
+        // Tabulate the sky once, both to sample it and to light the ground with it.
+        let mut table = Vec::with_capacity(TABLE_WIDTH * TABLE_HEIGHT);
+        let mut sky_irradiance = Color::black();
+        for y in 0..TABLE_HEIGHT {
+            for x in 0..TABLE_WIDTH {
+                let uv = (
+                    (x as f64 + 0.5) / TABLE_WIDTH as f64,
+                    (y as f64 + 0.5) / TABLE_HEIGHT as f64,
+                );
+                let direction = equirect_to_direction(uv, 0.0);
+                let radiance = model.radiance(&direction);
+
+                let solid_angle = 2.0
+                    * std::f64::consts::PI
+                    * std::f64::consts::PI
+                    * (uv.1 * std::f64::consts::PI).sin()
+                    / (TABLE_WIDTH * TABLE_HEIGHT) as f64;
+                sky_irradiance = sky_irradiance + radiance * (direction.y * solid_angle) as f32;
+                table.push(radiance);
+            }
+        }
+
+        let sun_irradiance = sun_radiance * (sun_direction.y.max(0.0) * sun_solid_angle()) as f32;
+        let ground_radiance =
+            ground_albedo * (sky_irradiance + sun_irradiance) * std::f32::consts::FRAC_1_PI;
+
+        let func: Vec<f64> = table
+            .iter()
+            .enumerate()
+            .map(|(i, radiance)| {
+                let v = ((i / TABLE_WIDTH) as f64 + 0.5) / TABLE_HEIGHT as f64;
+                let radiance = if v < 0.5 { *radiance } else { ground_radiance };
+                radiance.luminance() as f64 * (v * std::f64::consts::PI).sin()
+            })
+            .collect();
+
+        SkyLight {
+            sun_direction,
+            intensity,
+            light_group: DEFAULT_LIGHT_GROUP,
+            model,
+            sun_radiance,
+            ground_radiance,
+            distribution: Distribution2D::new(&func, TABLE_WIDTH, TABLE_HEIGHT),
+        }
+    }
+
+    /// The sun disk matching this sky.
+    pub fn sun(&self) -> SunLight {
+        SunLight {
+            direction: self.sun_direction,
+            radiance: self.sun_radiance * self.intensity,
+            angular_radius: SUN_ANGULAR_RADIUS,
+            light_group: self.light_group,
+        }
+    }
+
+    fn radiance(&self, direction: &Vector3) -> Color {
+        let radiance = if direction.y > 0.0 {
+            self.model.radiance(direction)
+        } else {
+            self.ground_radiance
+        };
+
+        radiance * self.intensity
+    }
+}
+
+// Luminance and xy chromaticity of the sky, at the zenith and as Perez distributions.
+struct Preetham {
+    sun_direction: Vector3,
+    theta_sun: f64,
+    zenith: [f64; 3],
+    perez: [[f64; 5]; 3],
+}
+
+impl Preetham {
+    fn new(sun_direction: Vector3, turbidity: f64) -> Preetham {
+        // The model only covers a sun above the horizon.
+        let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+        let t = turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f64::consts::PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = zenith_chromaticity(
+            t,
+            theta_sun,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let zenith_y = zenith_chromaticity(
+            t,
+            theta_sun,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+
+        Preetham {
+            sun_direction,
+            theta_sun,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez: [
+                [
+                    0.1787 * t - 1.4630,
+                    -0.3554 * t + 0.4275,
+                    -0.0227 * t + 5.3251,
+                    0.1206 * t - 2.5771,
+                    -0.0670 * t + 0.3703,
+                ],
+                [
+                    -0.0193 * t - 0.2592,
+                    -0.0665 * t + 0.0008,
+                    -0.0004 * t + 0.2125,
+                    -0.0641 * t - 0.8989,
+                    -0.0033 * t + 0.0452,
+                ],
+                [
+                    -0.0167 * t - 0.2608,
+                    -0.0950 * t + 0.0092,
+                    -0.0079 * t + 0.2102,
+                    -0.0441 * t - 1.6537,
+                    -0.0109 * t + 0.0529,
+                ],
+            ],
+        }
+    }
+
+    // Sky radiance for a unit direction, black below the horizon.
+    fn radiance(&self, direction: &Vector3) -> Color {
+        if direction.y <= 0.0 {
+            return Color::black();
+        }
+
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |channel: usize| {
+            let coefficients = &self.perez[channel];
+            self.zenith[channel] * perez(coefficients, direction.y, gamma)
+                / perez(coefficients, 1.0, self.theta_sun)
+        };
+
+        let luminance = value(0);
+        let x = value(1);
+        let y = value(2);
+        if y <= 0.0 {
+            return Color::black();
+        }
+
+        Color::from_xyz(x / y * luminance, luminance, (1.0 - x - y) / y * luminance)
+    }
+}
+
+impl Light for SkyLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, _point: &Point, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (direction, _, pdf) = sample_equirect(&self.distribution, sampler.get_2d(), 0.0)?;
+
+        Some(LightSample {
+            radiance: self.radiance(&direction),
+            direction,
+            distance: f64::INFINITY,
+            pdf,
+        })
+    }
+
+    fn le(&self, direction: &Vector3) -> Color {
+        self.radiance(&direction.normalize())
+    }
+}
+
+/// Distant disk light, sampled uniformly over the cone it subtends so shadows are soft.
+pub struct SunLight {
+    /// Unit direction towards the sun.
+    pub direction: Vector3,
+    pub radiance: Color,
+    /// Angular radius of the disk, in degrees.
+    pub angular_radius: f64,
+    pub light_group: u32,
+}
+
+impl SunLight {
+    fn cos_max(&self) -> f64 {
+        self.angular_radius.to_radians().cos()
+    }
+}
+
+impl Light for SunLight {
+    fn light_group(&self) -> u32 {
+        self.light_group
+    }
+
+    fn sample_incident(&self, _point: &Point, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let cos_max = self.cos_max();
+        let local = uniform_sample_cone(sampler.get_2d(), cos_max);
+
+        Some(LightSample {
+            radiance: self.radiance,
+            direction: to_world(&local, &self.direction),
+            distance: f64::INFINITY,
+            pdf: uniform_cone_pdf(cos_max),
+        })
+    }
+
+    fn le(&self, direction: &Vector3) -> Color {
+        if direction.normalize().dot(&self.direction) >= self.cos_max() {
+            self.radiance
+        } else {
+            Color::black()
+        }
+    }
+}
+
+fn sun_solid_angle() -> f64 {
+    2.0 * std::f64::consts::PI * (1.0 - SUN_ANGULAR_RADIUS.to_radians().cos())
+}
+
+/// Perez et al. sky luminance distribution for the angle `gamma` from the sun.
+fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+fn zenith_chromaticity(turbidity: f64, theta_sun: f64, coefficients: [[f64; 4]; 3]) -> f64 {
+    let thetas = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+    let weights = [turbidity * turbidity, turbidity, 1.0];
+
+    coefficients
+        .iter()
+        .zip(weights.iter())
+        .map(|(row, weight)| {
+            weight
+                * row
+                    .iter()
+                    .zip(thetas.iter())
+                    .map(|(c, t)| c * t)
+                    .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Sun radiance after Rayleigh and aerosol extinction along the path through the atmosphere.
+fn sun_radiance(theta_sun: f64, turbidity: f64, above_horizon: bool) -> Color {
+    if !above_horizon {
+        return Color::black();
+    }
+
+    // Kasten's relative optical air mass.
+    let degrees = theta_sun.to_degrees();
+    let air_mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let transmittance = |lambda: f64| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        (rayleigh * aerosol * SOLAR_ILLUMINANCE / sun_solid_angle()) as f32
+    };
+
+    Color {
+        r: transmittance(WAVELENGTHS[0]),
+        g: transmittance(WAVELENGTHS[1]),
+        b: transmittance(WAVELENGTHS[2]),
+    }
+}
@@ -0,0 +1,445 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use super::color::Color;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Range of wavelengths sampled for light split by dispersion, outside of it the eye barely sees
+/// anything.
+pub const VISIBLE_MIN: f64 = 380.0;
+pub const VISIBLE_MAX: f64 = 780.0;
+
+/// Helium d line, where catalog indices of refraction are given.
+pub const REFERENCE_WAVELENGTH: f64 = 587.56;
+
+// Planck, speed of light and Boltzmann constants in SI units.
+const PLANCK: f64 = 6.626_070_15e-34;
+const LIGHT_SPEED: f64 = 2.997_924_58e8;
+const BOLTZMANN: f64 = 1.380_649e-23;
+
+/// CIE 1931 color matching functions at `lambda` nanometers, using the multi-lobe gaussian fit
+/// of Wyman, Sloan and Shirley.
+pub fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * t * t).exp()
+    };
+
+    (
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Spectral radiance of a blackbody at `kelvin` for `lambda` nanometers, in W/(sr m² m).
+pub fn planck(lambda: f64, kelvin: f64) -> f64 {
+    let lambda = lambda * 1e-9;
+    let exponent = PLANCK * LIGHT_SPEED / (lambda * BOLTZMANN * kelvin);
+
+    2.0 * PLANCK * LIGHT_SPEED * LIGHT_SPEED / (lambda.powi(5) * (exponent.exp() - 1.0))
+}
+
+/// Linear RGB of a blackbody at `kelvin`, normalized to unit luminance.
+pub fn blackbody_color(kelvin: f64) -> Color {
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let radiance = planck(lambda, kelvin);
+        let (xb, yb, zb) = cie_xyz(lambda);
+        x += xb * radiance;
+        y += yb * radiance;
+        z += zb * radiance;
+        lambda += 1.0;
+    }
+
+    if y <= 0.0 {
+        return Color::black();
+    }
+    Color::from_xyz(x / y, 1.0, z / y)
+}
+
+/// Wavelength uniformly distributed over the visible range.
+pub fn sample_visible_wavelength(u: f64) -> f64 {
+    VISIBLE_MIN + u * (VISIBLE_MAX - VISIBLE_MIN)
+}
+
+/// Linear RGB carried by a path restricted to the single wavelength `lambda`, sampled by
+/// `sample_visible_wavelength`. Averaged over the wavelengths it is white, so that splitting
+/// light into its wavelengths keeps its color.
+pub fn wavelength_color(lambda: f64) -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let steps = (VISIBLE_MAX - VISIBLE_MIN) as usize;
+        let total = (0..steps).fold(Color::black(), |acc, step| {
+            acc + monochromatic_color(VISIBLE_MIN + step as f64 + 0.5)
+        });
+        total * (1.0 / steps as f32)
+    });
+
+    let color = monochromatic_color(lambda);
+    Color {
+        r: color.r / white.r,
+        g: color.g / white.g,
+        b: color.b / white.b,
+    }
+}
+
+fn monochromatic_color(lambda: f64) -> Color {
+    let (x, y, z) = cie_xyz(lambda);
+
+    Color::from_xyz(x, y, z)
+}
+
+/// Index of refraction varying with the wavelength, which splits white light into a rainbow.
+/// Wavelengths are in micrometers in both formulas.
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    /// `a + b / lambda²`.
+    Cauchy { a: f64, b: f64 },
+    /// `sqrt(1 + sum(b lambda² / (lambda² - c)))`.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, the common borosilicate crown glass.
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+            c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+        }
+    }
+
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [4.3356, 0.3306, 0.0],
+            c: [0.106 * 0.106, 0.175 * 0.175, 0.0],
+        }
+    }
+
+    /// Index of refraction at `lambda` nanometers.
+    pub fn index(&self, lambda: f64) -> f64 {
+        let lambda = lambda * 1e-3;
+        let lambda2 = lambda * lambda;
+
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / lambda2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b
+                    .iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    /// Index of refraction quoted for the material, at `REFERENCE_WAVELENGTH`.
+    pub fn reference_index(&self) -> f64 {
+        self.index(REFERENCE_WAVELENGTH)
+    }
+}
+
+impl FromStr for Dispersion {
+    type Err = String;
+
+    /// Parses `bk7`, `diamond` or the Cauchy coefficients `a,b`.
+    fn from_str(s: &str) -> Result<Dispersion, String> {
+        match s {
+            "bk7" => Ok(Dispersion::bk7()),
+            "diamond" => Ok(Dispersion::diamond()),
+            _ => {
+                let coefficients: Result<Vec<f64>, _> = s.split(',').map(str::parse).collect();
+                match coefficients {
+                    Ok(ref ab) if ab.len() == 2 => Ok(Dispersion::Cauchy { a: ab[0], b: ab[1] }),
+                    _ => Err(format!("Unknown dispersion: {}", s)),
+                }
+            }
+        }
+    }
+}
+
+/// Integral of the CIE luminance curve over the visible range, the luminance of a constant
+/// unit spectrum.
+fn y_integral() -> f64 {
+    static INTEGRAL: OnceLock<f64> = OnceLock::new();
+
+    *INTEGRAL.get_or_init(|| integrate_visible(|lambda| cie_xyz(lambda).1))
+}
+
+// Sum of `f` at every nanometer of the visible range.
+fn integrate_visible<F: Fn(f64) -> f64>(f: F) -> f64 {
+    let steps = (VISIBLE_MAX - VISIBLE_MIN) as usize;
+
+    (0..steps)
+        .map(|step| f(VISIBLE_MIN + step as f64 + 0.5))
+        .sum()
+}
+
+/// Spectral power distribution of an emitter, normalized to unit luminance.
+#[derive(Clone, Copy, Debug)]
+pub enum Spectrum {
+    Blackbody { kelvin: f64, scale: f64 },
+}
+
+impl Spectrum {
+    pub fn blackbody(kelvin: f64) -> Spectrum {
+        let luminance = integrate_visible(|lambda| planck(lambda, kelvin) * cie_xyz(lambda).1);
+
+        Spectrum::Blackbody {
+            kelvin,
+            scale: y_integral() / luminance,
+        }
+    }
+
+    /// Value at `lambda` nanometers.
+    pub fn value(&self, lambda: f64) -> f64 {
+        match *self {
+            Spectrum::Blackbody { kelvin, scale } => planck(lambda, kelvin) * scale,
+        }
+    }
+}
+
+// Illuminant white lights are upsampled under, a blackbody close to D65 so that RGB white stays
+// white.
+fn white_illuminant() -> Spectrum {
+    static WHITE: OnceLock<Spectrum> = OnceLock::new();
+
+    *WHITE.get_or_init(|| Spectrum::blackbody(6504.0))
+}
+
+// Smits' basis spectra for upsampling reflectances, ten bins over 380-720 nm.
+const SMITS_MIN: f64 = 380.0;
+const SMITS_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// Basis spectrum at `lambda`, interpolated between the bin centers.
+fn smits_basis(basis: &[f64; 10], lambda: f64) -> f64 {
+    let bins = basis.len();
+    let x = (lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * bins as f64 - 0.5;
+    let x = x.clamp(0.0, (bins - 1) as f64);
+    let index = (x as usize).min(bins - 2);
+    let t = x - index as f64;
+
+    basis[index] * (1.0 - t) + basis[index + 1] * t
+}
+
+/// Smooth reflectance spectrum with the linear RGB `color`, evaluated at `lambda` nanometers,
+/// after Smits' "An RGB to spectrum conversion for reflectances".
+pub fn rgb_to_spectrum(color: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (color.r as f64, color.g as f64, color.b as f64);
+    let basis = |spectrum: &[f64; 10]| smits_basis(spectrum, lambda);
+
+    if r <= g && r <= b {
+        r * basis(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+            } else {
+                (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * basis(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+            } else {
+                (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+            }
+    } else {
+        b * basis(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+            } else {
+                (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+            }
+    }
+}
+
+/// Wavelengths followed by a path in spectral mode, one per channel of the colors it carries:
+/// the red channel holds the value at the first wavelength, green at the second and blue at the
+/// third.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; 3],
+    /// Density of each wavelength, zero once it stopped being followed.
+    pub pdf: [f64; 3],
+}
+
+impl SampledWavelengths {
+    /// A uniformly distributed wavelength and two more evenly spaced from it over the visible
+    /// range.
+    pub fn sample(u: f64) -> SampledWavelengths {
+        let range = VISIBLE_MAX - VISIBLE_MIN;
+        let first = sample_visible_wavelength(u);
+        let mut lambda = [first; 3];
+        for (i, lambda) in lambda.iter_mut().enumerate().skip(1) {
+            *lambda = first + i as f64 * range / 3.0;
+            if *lambda > VISIBLE_MAX {
+                *lambda -= range;
+            }
+        }
+
+        SampledWavelengths {
+            lambda,
+            pdf: [1.0 / range; 3],
+        }
+    }
+
+    /// Keeps only the first wavelength, once the path took a direction that only holds for it.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0.0 {
+            return;
+        }
+
+        self.pdf[0] /= 3.0;
+        self.pdf[1] = 0.0;
+        self.pdf[2] = 0.0;
+    }
+
+    fn map<F: Fn(f64) -> f64>(&self, f: F) -> Color {
+        Color {
+            r: f(self.lambda[0]) as f32,
+            g: f(self.lambda[1]) as f32,
+            b: f(self.lambda[2]) as f32,
+        }
+    }
+
+    /// Reflectance with the linear RGB `color`, at the sampled wavelengths.
+    pub fn reflectance(&self, color: &Color) -> Color {
+        self.map(|lambda| rgb_to_spectrum(color, lambda))
+    }
+
+    /// Radiance of a light with the linear RGB `color`, at the sampled wavelengths. Lights with
+    /// their own `spectrum` keep its shape, scaled to the luminance of `color`.
+    pub fn emission(&self, color: &Color, spectrum: Option<&Spectrum>) -> Color {
+        match spectrum {
+            Some(spectrum) => {
+                let luminance = color.luminance() as f64;
+                self.map(|lambda| spectrum.value(lambda) * luminance)
+            }
+            None => {
+                let white = white_illuminant();
+                self.map(|lambda| rgb_to_spectrum(color, lambda) * white.value(lambda))
+            }
+        }
+    }
+
+    /// CIE XYZ tristimulus estimated from `radiance` at the sampled wavelengths, in the
+    /// channels of a `Color`. A constant unit spectrum has unit luminance.
+    pub fn xyz(&self, radiance: &Color) -> Color {
+        let values = [radiance.r, radiance.g, radiance.b];
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for ((&lambda, &pdf), &value) in self.lambda.iter().zip(self.pdf.iter()).zip(values.iter())
+        {
+            if pdf == 0.0 {
+                continue;
+            }
+
+            let (xb, yb, zb) = cie_xyz(lambda);
+            let weight = value as f64 / pdf;
+            x += xb * weight;
+            y += yb * weight;
+            z += zb * weight;
+        }
+
+        let scale = 1.0 / (3.0 * y_integral());
+        Color {
+            r: (x * scale) as f32,
+            g: (y * scale) as f32,
+            b: (z * scale) as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blackbody_spectrum_has_unit_luminance() {
+        for &kelvin in &[2700.0, 6500.0, 10000.0] {
+            let spectrum = Spectrum::blackbody(kelvin);
+            let luminance = integrate_visible(|lambda| spectrum.value(lambda) * cie_xyz(lambda).1);
+            assert!((luminance / y_integral() - 1.0).abs() < 1e-9, "{}", kelvin);
+        }
+    }
+
+    #[test]
+    fn blackbody_color_has_unit_luminance() {
+        for &kelvin in &[2700.0, 6500.0, 10000.0] {
+            let luminance = blackbody_color(kelvin).luminance();
+            assert!((luminance - 1.0).abs() < 1e-3, "{}: {}", kelvin, luminance);
+        }
+    }
+
+    // Schott N-BK7 catalog indices at the F, d and C lines.
+    const BK7: [(f64, f64); 3] = [(486.13, 1.5224), (587.56, 1.5168), (656.27, 1.5143)];
+
+    #[test]
+    fn sellmeier_matches_the_bk7_catalog() {
+        let bk7 = Dispersion::bk7();
+
+        for &(lambda, index) in &BK7 {
+            assert!((bk7.index(lambda) - index).abs() < 1e-4, "{}", lambda);
+        }
+        assert!((bk7.reference_index() - 1.5168).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cauchy_fit_approximates_bk7() {
+        let bk7: Dispersion = "1.5046,0.0042".parse().unwrap();
+
+        for &(lambda, index) in &BK7 {
+            assert!((bk7.index(lambda) - index).abs() < 1e-3, "{}", lambda);
+        }
+        assert!("1.5".parse::<Dispersion>().is_err());
+    }
+
+    // Average of the estimates over stratified wavelength samples.
+    fn average_xyz(radiance: &Color, terminate: bool) -> Color {
+        let count = 1000;
+        (0..count).fold(Color::black(), |acc, i| {
+            let mut wavelengths = SampledWavelengths::sample((i as f64 + 0.5) / count as f64);
+            if terminate {
+                wavelengths.terminate_secondary();
+            }
+            acc + wavelengths.xyz(radiance) * (1.0 / count as f32)
+        })
+    }
+
+    #[test]
+    fn constant_unit_spectrum_has_unit_luminance() {
+        for &terminate in &[false, true] {
+            let xyz = average_xyz(&Color::white(), terminate);
+            assert!((xyz.g - 1.0).abs() < 1e-3, "{}", xyz.g);
+            // Equal energy white.
+            assert!((xyz.r - 1.0).abs() < 2e-2, "{}", xyz.r);
+            assert!((xyz.b - 1.0).abs() < 2e-2, "{}", xyz.b);
+        }
+
+        let xyz = average_xyz(&(Color::white() * 2.0), false);
+        assert!((xyz.g - 2.0).abs() < 2e-3, "{}", xyz.g);
+    }
+}